@@ -1,7 +1,13 @@
 use breakwater::{
     framebuffer::FrameBuffer,
-    parser::{from_hex_char_lookup, from_hex_char_map, parse_pixelflut_commands, ParserState},
-    test::helpers::{get_commands_to_draw_rect, DevNullTcpStream},
+    parser::{
+        from_hex_char_lookup, from_hex_char_map, original::OriginalParser,
+        reference::ReferenceParser, Parser,
+    },
+    test::helpers::{
+        get_binary_commands_to_draw_rect, get_commands_to_draw_rect,
+        get_commands_to_draw_rect_with_alpha, DevNullTcpStream,
+    },
 };
 use criterion::{
     BenchmarkId, Criterion, {criterion_group, criterion_main},
@@ -11,13 +17,9 @@ use std::{sync::Arc, time::Duration};
 const FRAMEBUFFER_WIDTH: usize = 1920;
 const FRAMEBUFFER_HEIGHT: usize = 1080;
 
-async fn invoke_parse_pixelflut_commands(
-    input: &[u8],
-    fb: &Arc<FrameBuffer>,
-    parser_state: ParserState,
-) {
+async fn invoke_parse_pixelflut_commands(input: &[u8], mut parser: impl Parser) {
     let mut stream = DevNullTcpStream::default();
-    parse_pixelflut_commands(input, fb, &mut stream, parser_state).await;
+    parser.parse(input, &mut stream).await;
 }
 
 #[allow(unused)] // Benchmarks are commented out by default
@@ -72,9 +74,114 @@ fn from_elem(c: &mut Criterion) {
         &draw_commands,
         |b, input| {
             let fb = Arc::new(FrameBuffer::new(FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT));
-            let parser_state = ParserState::default();
             b.to_async(tokio::runtime::Runtime::new().unwrap())
-                .iter(|| invoke_parse_pixelflut_commands(input, &fb, parser_state.clone()));
+                .iter(|| {
+                    invoke_parse_pixelflut_commands(input, OriginalParser::new(Arc::clone(&fb), 0))
+                });
+        },
+    );
+
+    c.bench_with_input(
+        BenchmarkId::new(
+            "parse_draw_commands_padded_framebuffer",
+            format!("{FRAMEBUFFER_WIDTH} x {FRAMEBUFFER_HEIGHT}"),
+        ),
+        &draw_commands,
+        |b, input| {
+            let fb = Arc::new(FrameBuffer::new_padded(
+                FRAMEBUFFER_WIDTH,
+                FRAMEBUFFER_HEIGHT,
+            ));
+            b.to_async(tokio::runtime::Runtime::new().unwrap())
+                .iter(|| {
+                    invoke_parse_pixelflut_commands(
+                        input,
+                        OriginalParser::new_padded(Arc::clone(&fb), 0),
+                    )
+                });
+        },
+    );
+
+    c.bench_with_input(
+        BenchmarkId::new(
+            "parse_draw_commands_reference_parser",
+            format!("{FRAMEBUFFER_WIDTH} x {FRAMEBUFFER_HEIGHT}"),
+        ),
+        &draw_commands,
+        |b, input| {
+            let fb = Arc::new(FrameBuffer::new(FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT));
+            b.to_async(tokio::runtime::Runtime::new().unwrap())
+                .iter(|| {
+                    invoke_parse_pixelflut_commands(input, ReferenceParser::new(Arc::clone(&fb), 0))
+                });
+        },
+    );
+
+    for alpha in [0xff, 0x80, 0x00] {
+        let draw_commands = get_commands_to_draw_rect_with_alpha(
+            FRAMEBUFFER_WIDTH,
+            FRAMEBUFFER_HEIGHT,
+            0x123456,
+            alpha,
+        );
+        let draw_commands = draw_commands.as_bytes();
+
+        c.bench_with_input(
+            BenchmarkId::new(
+                "parse_draw_commands_with_alpha",
+                format!("{FRAMEBUFFER_WIDTH} x {FRAMEBUFFER_HEIGHT} alpha {alpha:02x}"),
+            ),
+            &draw_commands,
+            |b, input| {
+                let fb = Arc::new(FrameBuffer::new(FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT));
+                b.to_async(tokio::runtime::Runtime::new().unwrap())
+                    .iter(|| {
+                        invoke_parse_pixelflut_commands(
+                            input,
+                            OriginalParser::new(Arc::clone(&fb), 0),
+                        )
+                    });
+            },
+        );
+    }
+
+    let binary_draw_commands =
+        get_binary_commands_to_draw_rect(FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT, 0x123456);
+    let binary_draw_commands = binary_draw_commands.as_slice();
+
+    c.bench_with_input(
+        BenchmarkId::new(
+            "parse_binary_draw_commands",
+            format!("{FRAMEBUFFER_WIDTH} x {FRAMEBUFFER_HEIGHT}"),
+        ),
+        &binary_draw_commands,
+        |b, input| {
+            let fb = Arc::new(FrameBuffer::new(FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT));
+            b.to_async(tokio::runtime::Runtime::new().unwrap())
+                .iter(|| {
+                    invoke_parse_pixelflut_commands(input, OriginalParser::new(Arc::clone(&fb), 0))
+                });
+        },
+    );
+
+    c.bench_with_input(
+        BenchmarkId::new(
+            "parse_binary_draw_commands_padded_framebuffer",
+            format!("{FRAMEBUFFER_WIDTH} x {FRAMEBUFFER_HEIGHT}"),
+        ),
+        &binary_draw_commands,
+        |b, input| {
+            let fb = Arc::new(FrameBuffer::new_padded(
+                FRAMEBUFFER_WIDTH,
+                FRAMEBUFFER_HEIGHT,
+            ));
+            b.to_async(tokio::runtime::Runtime::new().unwrap())
+                .iter(|| {
+                    invoke_parse_pixelflut_commands(
+                        input,
+                        OriginalParser::new_padded(Arc::clone(&fb), 0),
+                    )
+                });
         },
     );
 
@@ -89,9 +196,8 @@ fn from_elem(c: &mut Criterion) {
     //     &read_commands,
     //     |b, input| {
     //         let fb = Arc::new(FrameBuffer::new(FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT));
-    //         let parser_state = ParserState::default();
     //         b.to_async(tokio::runtime::Runtime::new().unwrap())
-    //             .iter(|| invoke_parse_pixelflut_commands(input, &fb, parser_state.clone()));
+    //             .iter(|| invoke_parse_pixelflut_commands(input, OriginalParser::new(Arc::clone(&fb), 0)));
     //     },
     // );
 
@@ -120,3 +226,15 @@ criterion_main!(benches);
 // Check for command by reading u32                                 25.590 ms   25.327 ms   23.174 ms
 // => Accepted the change :) So far we have only read changed the parsing for "PX " logic, lets also change the other parsing logics
 // Check for command by reading u32 everywhere                      24.465 ms   23.435 ms   22.087 ms
+
+// Padded framebuffer without bound checks in FrameBuffer::set (parse_draw_commands vs parse_draw_commands_padded_framebuffer)
+// Bound checked                                                    45.576 ms
+// Padded                                                           42.406 ms
+// => Roughly the 5% the flamegraph promised. Binary commands are dominated by other things (19.273 ms vs 18.956 ms)
+
+// FrameBuffer backed by AtomicU32 (relaxed) instead of UnsafeCell<Vec<u32>>, measured back to back on the same machine
+// UnsafeCell<Vec<u32>>                                             47.618 ms   56.167 ms
+// AtomicU32                                                        51.473 ms   50.971 ms
+// => Comparing the best runs AtomicU32 is about 7% slower (50.971 ms vs 47.618 ms). The UnsafeCell runs differ by 18%
+//    among themselves, so more runs are needed to pin this down. Accepted, as concurrent connections writing to the
+//    UnsafeCell are a data race and therefore undefined behavior
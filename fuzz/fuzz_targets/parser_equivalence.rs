@@ -0,0 +1,18 @@
+//! Run with `cargo +nightly fuzz run parser_equivalence`
+#![no_main]
+
+use breakwater::test::helpers::assert_parsers_are_equivalent;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (Vec<u8>, Vec<u8>)| {
+    let (chunk_sizes, data) = input;
+    let chunk_sizes = chunk_sizes
+        .into_iter()
+        .map(|size| size as usize)
+        .collect::<Vec<_>>();
+
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(assert_parsers_are_equivalent(&data, &chunk_sizes));
+});
@@ -0,0 +1,377 @@
+use std::{
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr},
+    path::Path,
+    str::FromStr,
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+
+use log::{info, warn};
+use tokio::sync::watch;
+
+use crate::connection_limiter::ipv6_prefix;
+
+/// How often the access list file is checked for changes
+const FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Decides which clients are allowed to connect, based on lists of IPs and CIDR ranges.
+///
+/// The rules are read from a file with one rule per line, e.g.
+///
+/// ```text
+/// # Only allow the event network ...
+/// allow 10.0.0.0/8
+/// allow 2001:db8::/32
+/// # ... except for this one client
+/// deny 10.1.2.3
+/// ```
+///
+/// An IP is denied if it matches any `deny` rule. If there are `allow` rules, it additionally needs to match one of them.
+/// The IP needs to be canonical, so that IPv4 addresses mapped into IPv6 are matched against the IPv4 rules.
+#[derive(Debug)]
+pub struct AccessList {
+    rules: RwLock<AccessRules>,
+    /// Lets open connections know that the rules changed, so that they can check if their IP got denied
+    reloaded_tx: watch::Sender<()>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct AccessRules {
+    allow: Vec<IpNetwork>,
+    deny: Vec<IpNetwork>,
+}
+
+/// An IP address range in CIDR notation, e.g. `10.0.0.0/8`. A single IP is a range with the full prefix length.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl AccessList {
+    pub fn new(rules: AccessRules) -> Self {
+        AccessList {
+            rules: RwLock::new(rules),
+            reloaded_tx: watch::Sender::new(()),
+        }
+    }
+
+    /// Returns an access list that allows everyone.
+    pub fn allow_all() -> Self {
+        AccessList::new(AccessRules::default())
+    }
+
+    pub async fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(AccessList::new(AccessRules::load(path).await?))
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        self.rules
+            .read()
+            .expect("Access list poisoned")
+            .is_allowed(ip)
+    }
+
+    /// Replaces the rules. Connections from IPs that are no longer allowed get closed.
+    pub fn set_rules(&self, rules: AccessRules) {
+        *self.rules.write().expect("Access list poisoned") = rules;
+        self.reloaded_tx.send_replace(());
+    }
+
+    /// Completes once the IP is denied, either right away or after a change of the rules.
+    pub async fn denied(&self, ip: IpAddr) {
+        // Subscribe before checking, so that we don't miss a change in between
+        let mut reloaded_rx = self.reloaded_tx.subscribe();
+        if !self.is_allowed(ip) {
+            return;
+        }
+        // The sender lives as long as we do, so this never fails
+        while reloaded_rx.changed().await.is_ok() {
+            if !self.is_allowed(ip) {
+                return;
+            }
+        }
+    }
+
+    /// Reloads the rules from the file whenever it changes or SIGHUP is received. Never returns.
+    /// If the file can't be read or contains invalid rules, the current rules are kept.
+    pub async fn follow_file(&self, path: &Path) {
+        #[cfg(unix)]
+        let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())
+            .expect("Failed to install SIGHUP handler");
+        let mut last_modified = modified(path).await;
+
+        loop {
+            #[cfg(unix)]
+            let reload_requested = tokio::select! {
+                _ = sighup.recv() => true,
+                _ = tokio::time::sleep(FILE_POLL_INTERVAL) => false,
+            };
+            #[cfg(not(unix))]
+            let reload_requested = {
+                tokio::time::sleep(FILE_POLL_INTERVAL).await;
+                false
+            };
+
+            let current_modified = modified(path).await;
+            if !reload_requested && current_modified == last_modified {
+                continue;
+            }
+            last_modified = current_modified;
+
+            match AccessRules::load(path).await {
+                Ok(rules) => {
+                    info!("Reloaded access list from {} ({rules})", path.display());
+                    self.set_rules(rules);
+                }
+                Err(err) => warn!(
+                    "Failed to reload access list from {}, keeping the current rules: {err}",
+                    path.display()
+                ),
+            }
+        }
+    }
+}
+
+/// Returns [`None`] if the file doesn't exist (anymore)
+async fn modified(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path)
+        .await
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+impl AccessRules {
+    pub async fn load(path: &Path) -> std::io::Result<Self> {
+        tokio::fs::read_to_string(path).await?.parse()
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        if self.deny.iter().any(|network| network.contains(ip)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|network| network.contains(ip))
+    }
+}
+
+impl FromStr for AccessRules {
+    type Err = std::io::Error;
+
+    fn from_str(content: &str) -> std::io::Result<Self> {
+        let mut rules = AccessRules::default();
+
+        for (line_number, line) in content.lines().enumerate() {
+            let invalid_rule = |reason: String| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("Invalid rule in line {}: {reason}", line_number + 1),
+                )
+            };
+
+            // Everything after a # is a comment
+            let rule = line.split('#').next().unwrap_or_default().trim();
+            if rule.is_empty() {
+                continue;
+            }
+
+            let Some((action, network)) = rule.split_once(char::is_whitespace) else {
+                return Err(invalid_rule(format!(
+                    "Expected \"allow <network>\" or \"deny <network>\", got \"{rule}\""
+                )));
+            };
+            let network = network.trim().parse().map_err(invalid_rule)?;
+            match action {
+                "allow" => rules.allow.push(network),
+                "deny" => rules.deny.push(network),
+                _ => {
+                    return Err(invalid_rule(format!(
+                        "Unknown action \"{action}\", expected \"allow\" or \"deny\""
+                    )))
+                }
+            }
+        }
+
+        Ok(rules)
+    }
+}
+
+impl Display for AccessRules {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} allow and {} deny rules",
+            self.allow.len(),
+            self.deny.len()
+        )
+    }
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_length as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                ipv6_prefix(network, self.prefix_length) == ipv6_prefix(ip, self.prefix_length)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = String;
+
+    fn from_str(network: &str) -> Result<Self, Self::Err> {
+        let (address, prefix_length) = match network.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length)),
+            None => (network, None),
+        };
+        let address: IpAddr = address
+            .parse()
+            .map_err(|err| format!("Invalid IP address \"{address}\": {err}"))?;
+        let max_prefix_length = match address {
+            IpAddr::V4(_) => Ipv4Addr::BITS as u8,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_length = match prefix_length {
+            Some(prefix_length) => prefix_length
+                .parse()
+                .ok()
+                .filter(|prefix_length| *prefix_length <= max_prefix_length)
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid prefix length \"{prefix_length}\", must be between 0 and {max_prefix_length}"
+                    )
+                })?,
+            None => max_prefix_length,
+        };
+
+        Ok(IpNetwork {
+            address,
+            prefix_length,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+    use std::sync::Arc;
+
+    #[rstest]
+    #[case("10.0.0.0/8", "10.1.2.3", true)]
+    #[case("10.0.0.0/8", "11.0.0.0", false)]
+    #[case("10.1.2.3", "10.1.2.3", true)]
+    #[case("10.1.2.3", "10.1.2.4", false)]
+    #[case("0.0.0.0/0", "192.168.0.1", true)]
+    #[case("2001:db8::/32", "2001:db8:1::1", true)]
+    #[case("2001:db8::/32", "2001:db9::1", false)]
+    #[case("::/0", "10.0.0.1", false)]
+    #[case("0.0.0.0/0", "::1", false)]
+    fn test_network_contains(#[case] network: &str, #[case] ip: IpAddr, #[case] expected: bool) {
+        let network: IpNetwork = network.parse().unwrap();
+        assert_eq!(network.contains(ip), expected);
+    }
+
+    #[rstest]
+    #[case("10.0.0.0/33")]
+    #[case("2001:db8::/129")]
+    #[case("10.0.0.0/")]
+    #[case("10.0.0")]
+    #[case("localhost")]
+    fn test_invalid_network(#[case] network: &str) {
+        assert!(network.parse::<IpNetwork>().is_err());
+    }
+
+    #[rstest]
+    // Everything is allowed without rules
+    #[case("", "10.0.0.1", true)]
+    #[case("# Only comments\n\n", "2001:db8::1", true)]
+    #[case("deny 10.0.0.0/8", "10.0.0.1", false)]
+    #[case("deny 10.0.0.0/8", "192.168.0.1", true)]
+    // Allow rules make everything else denied
+    #[case("allow 10.0.0.0/8", "10.0.0.1", true)]
+    #[case("allow 10.0.0.0/8", "192.168.0.1", false)]
+    #[case("allow 10.0.0.0/8", "2001:db8::1", false)]
+    // Deny wins
+    #[case("allow 10.0.0.0/8\ndeny 10.1.2.3 # abusive client", "10.1.2.3", false)]
+    #[case("allow 10.0.0.0/8\ndeny 10.1.2.3 # abusive client", "10.1.2.4", true)]
+    #[case("  deny\t2001:db8::/32  ", "2001:db8::1", false)]
+    fn test_rules(#[case] rules: &str, #[case] ip: IpAddr, #[case] expected: bool) {
+        let rules: AccessRules = rules.parse().unwrap();
+        assert_eq!(rules.is_allowed(ip), expected);
+    }
+
+    #[rstest]
+    #[case("10.0.0.1", "line 1")]
+    #[case("allow 10.0.0.1\npermit 10.0.0.1", "line 2")]
+    #[case("# Comment\ndeny 10.0.0.1/40", "line 2")]
+    fn test_invalid_rules(#[case] rules: &str, #[case] expected_error: &str) {
+        let err = rules.parse::<AccessRules>().unwrap_err();
+        assert!(err.to_string().contains(expected_error), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_denied_after_reload() {
+        let access_list = Arc::new(AccessList::allow_all());
+        let ip: IpAddr = "10.1.2.3".parse().unwrap();
+
+        let waiting_access_list = Arc::clone(&access_list);
+        let denied = tokio::spawn(async move { waiting_access_list.denied(ip).await });
+
+        // Reloads that don't affect the IP keep the connection open
+        access_list.set_rules("deny 10.9.9.9".parse().unwrap());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!denied.is_finished());
+
+        access_list.set_rules("deny 10.0.0.0/8".parse().unwrap());
+        tokio::time::timeout(Duration::from_secs(5), denied)
+            .await
+            .expect("IP did not get denied")
+            .unwrap();
+        assert!(!access_list.is_allowed(ip));
+    }
+
+    #[tokio::test]
+    async fn test_follow_file() {
+        let path = std::env::temp_dir().join(format!(
+            "breakwater_test_{}_access_list",
+            std::process::id()
+        ));
+        std::fs::write(&path, "deny 10.0.0.1\n").unwrap();
+        let access_list = Arc::new(AccessList::load(&path).await.unwrap());
+        assert!(!access_list.is_allowed("10.0.0.1".parse().unwrap()));
+
+        let following_access_list = Arc::clone(&access_list);
+        let following_path = path.clone();
+        let follower =
+            tokio::spawn(async move { following_access_list.follow_file(&following_path).await });
+        // Make sure the change gets a different modification time
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Invalid files are ignored
+        std::fs::write(&path, "deny everyone\n").unwrap();
+        tokio::time::sleep(FILE_POLL_INTERVAL * 2).await;
+        assert!(!access_list.is_allowed("10.0.0.1".parse().unwrap()));
+
+        std::fs::write(&path, "deny 10.0.0.2\n").unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !access_list.is_allowed("10.0.0.1".parse().unwrap()) {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Access list did not get reloaded");
+        assert!(!access_list.is_allowed("10.0.0.2".parse().unwrap()));
+
+        follower.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
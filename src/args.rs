@@ -1,12 +1,43 @@
+use chrono::format::{Item, Numeric, StrftimeItems};
 use clap::Parser;
 
+use crate::{
+    background_image::BackgroundImageScaling,
+    parser::ParserImplementation,
+    rate_limiter::RateLimitUnit,
+    sinks::{ffmpeg::WebStreamFormat, snapshot::TimelapseFormat},
+};
+
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 pub struct Args {
-    /// Listen address to bind to.
+    /// Listen address to bind to, can be given multiple times to e.g. listen on separate network interfaces.
+    /// Addresses like `unix:/run/breakwater.sock` are Unix domain sockets, which are handy for fast local clients.
+    /// They are protected by the permissions of the socket file, so their clients bypass the access list and all limits.
     /// The default value will listen on all interfaces for IPv4 and IPv6 packets.
     #[clap(short, long, default_value = "[::]:1234")]
-    pub listen_address: String,
+    pub listen_address: Vec<String>,
+
+    /// Expect a PROXY protocol v1 or v2 header (as sent by e.g. HAProxy) at the start of every TCP connection and use the
+    /// client address from it instead of the address of the proxy. Connections without a valid header are closed.
+    /// Only enable this when running behind a proxy, as otherwise clients can pretend to be someone else.
+    #[clap(long)]
+    pub proxy_protocol: bool,
+
+    /// Listen address of the Pixelflut UDP server, e.g. `[::]:1234`.
+    /// Every datagram needs to contain complete commands.
+    #[clap(long)]
+    pub udp_listen_address: Option<String>,
+
+    /// Send responses of the UDP server (e.g. to `PX x y`) back to the source address.
+    /// Disabled by default, as the source address of UDP datagrams can be spoofed to use us for reflection attacks.
+    #[clap(long)]
+    pub udp_respond: bool,
+
+    /// Listen address of the Pixelflut WebSocket server for browser clients, e.g. `[::]:1235`.
+    /// Speaks the same protocol as the TCP server, every WebSocket message contains Pixelflut commands.
+    #[clap(long)]
+    pub websocket_listen_address: Option<String>,
 
     /// Width of the drawing surface.
     #[clap(long, default_value_t = 1280)]
@@ -16,8 +47,67 @@ pub struct Args {
     #[clap(long, default_value_t = 720)]
     pub height: usize,
 
+    /// PNG, JPEG or PPM image that is drawn onto the drawing surface at startup, e.g. a logo or template.
+    /// A canvas restored from the canvas save file is drawn over it.
+    #[clap(long)]
+    pub background_image: Option<String>,
+
+    /// How the background image is fitted onto the drawing surface, if it has a different size.
+    #[clap(long, value_enum, default_value_t = BackgroundImageScaling::default())]
+    pub background_image_scaling: BackgroundImageScaling,
+
+    /// Allocate the drawing surface with a guard column and row, so that pixels can be set without checking the bounds.
+    /// This speeds up drawing a bit at the cost of a slightly bigger framebuffer.
+    #[clap(long)]
+    pub padded_framebuffer: bool,
+
+    /// Maximum number of pixels (width * height) a single RECT command is allowed to fill.
+    /// Larger rectangles are ignored. Set to 0 to disable the RECT command.
+    #[clap(long, default_value_t = 10_000)]
+    pub max_rect_area: usize,
+
+    /// File containing `allow` and `deny` rules for IPs and CIDR ranges, one per line, e.g. `deny 10.0.0.0/8`.
+    /// If there are any `allow` rules, only matching clients can connect; `deny` rules always win.
+    /// The file is reloaded when it changes or on SIGHUP, open connections of newly denied clients are closed.
+    #[clap(long)]
+    pub access_list_file: Option<String>,
+
+    /// Maximum number of concurrent connections per IP address. Further connections are closed right away.
+    #[clap(long)]
+    pub max_connections_per_ip: Option<u32>,
+
+    /// Maximum number of concurrent connections per IPv6 prefix (see `--ipv6-prefix-length`), as a single host
+    /// usually has a whole IPv6 prefix at hand. Further connections are closed right away.
+    #[clap(long)]
+    pub max_connections_per_ipv6_prefix: Option<u32>,
+
+    /// Length of the IPv6 prefix that is treated as a single client, both for the connection limit and the rate limit.
+    #[clap(long, default_value_t = 64, value_parser = clap::value_parser!(u8).range(0..=128))]
+    pub ipv6_prefix_length: u8,
+
+    /// Maximum number of pixels or bytes (see `--rate-limit-unit`) per second and client, 0 disables the limit.
+    /// A client is an IP address or IPv6 prefix (see `--ipv6-prefix-length`), all of its TCP, WebSocket and UDP traffic
+    /// shares the limit. Clients exceeding the limit are slowed down by reading less from their connections, their UDP
+    /// datagrams are dropped.
+    #[clap(long, default_value_t = 0)]
+    pub rate_limit: u64,
+
+    /// What the rate limit is counted in.
+    #[clap(long, value_enum, default_value_t = RateLimitUnit::default())]
+    pub rate_limit_unit: RateLimitUnit,
+
+    /// File containing the rate limit as a single number, which takes precedence over `--rate-limit`.
+    /// Changes to the file are picked up at runtime, so the limit can be adjusted without restarting.
+    #[clap(long)]
+    pub rate_limit_file: Option<String>,
+
+    /// The parser implementation used for client connections.
+    #[clap(long, value_enum, default_value_t = ParserImplementation::default())]
+    pub parser: ParserImplementation,
+
     /// Frames per second the server should aim for.
-    #[clap(short, long, default_value_t = 30)]
+    /// Applies to all sinks, e.g. the VNC server and the ffmpeg video stream.
+    #[clap(short, long, default_value_t = 30, value_parser = clap::value_parser!(u32).range(1..))]
     pub fps: u32,
 
     /// Text to display on the screen.
@@ -49,17 +139,209 @@ pub struct Args {
     #[clap(long)]
     pub disable_statistics_save_file: bool,
 
+    /// Save file where the canvas is periodically saved.
+    /// The save file will be read during startup and the canvas is restored, as long as the width and height did not change.
+    /// To start with an empty canvas simply remove the file.
+    #[clap(long, default_value = "canvas.bin")]
+    pub canvas_save_file: String,
+
+    /// Interval (in seconds) in which the canvas save file should be updated.
+    #[clap(long, default_value = "60", value_parser = clap::value_parser!(u64).range(1..))]
+    pub canvas_save_interval_s: u64,
+
+    /// Disable periodical saving of the canvas into save file.
+    #[clap(long)]
+    pub disable_canvas_save_file: bool,
+
     /// Enable rtmp streaming to configured address, e.g. `rtmp://127.0.0.1:1935/live/test`
     #[clap(long)]
     pub rtmp_address: Option<String>,
 
-    /// Enable dump of video stream into file. File name will be `pixelflut_dump_{timestamp}.mp4
+    /// Enable dump of video stream into files. The recording is split into segments of `--video-segment-duration-s`.
+    /// mp4 files are written fragmented, so that they stay playable if breakwater crashes.
     #[clap(long)]
     pub save_video_to_file: bool,
 
+    /// Directory the video files are written into.
+    #[clap(long, default_value = ".")]
+    pub video_directory: String,
+
+    /// Name of the video files. Must contain strftime placeholders down to the seconds (e.g. `%S`), which are filled with
+    /// the time the segment started.
+    /// The container is chosen based on the extension, e.g. `.mp4` or `.mkv`.
+    #[clap(
+        long,
+        default_value = "pixelflut_dump_%Y-%m-%d_%H-%M-%S.mp4",
+        value_parser = parse_video_file_pattern
+    )]
+    pub video_file_pattern: String,
+
+    /// Start a new video file after this many seconds.
+    #[clap(long, default_value_t = 3600, value_parser = clap::value_parser!(u64).range(1..))]
+    pub video_segment_duration_s: u64,
+
+    /// Delete the oldest video files once all video files in the video directory together use more disk space than
+    /// this. The video file that is currently written is never deleted.
+    #[clap(long)]
+    pub video_max_disk_usage_mb: Option<u64>,
+
+    /// Enable a livestream that can be watched in a browser. Segments and playlist are written into the given
+    /// directory, which needs to be served by a web server.
+    #[clap(long)]
+    pub web_stream_directory: Option<String>,
+
+    /// Format of the web stream.
+    #[clap(long, value_enum, default_value_t = WebStreamFormat::Hls)]
+    pub web_stream_format: WebStreamFormat,
+
+    /// Length of a single segment of the web stream. Shorter segments reduce the delay, but increase the overhead.
+    #[clap(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    pub web_stream_segment_duration_s: u32,
+
+    /// Number of segments in the playlist of the web stream. Older segments get deleted.
+    #[clap(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub web_stream_playlist_size: u32,
+
+    /// Video codec used by ffmpeg.
+    #[clap(long, default_value = "libx264")]
+    pub ffmpeg_video_codec: String,
+
+    /// Encoder preset used by ffmpeg, trading encoding speed for compression.
+    #[clap(long, default_value = "veryfast")]
+    pub ffmpeg_preset: String,
+
+    /// Video bitrate used by ffmpeg.
+    #[clap(long, default_value = "4500k")]
+    pub ffmpeg_video_bitrate: String,
+
+    /// Number of frames between two keyframes. Defaults to two seconds worth of frames.
+    #[clap(long)]
+    pub ffmpeg_gop_size: Option<u32>,
+
+    /// Scale the video to the given resolution, e.g. `1280x720`. Defaults to the size of the drawing surface.
+    #[clap(long, value_parser = parse_resolution)]
+    pub ffmpeg_output_resolution: Option<(u32, u32)>,
+
+    /// Number of threads ffmpeg uses for encoding.
+    #[clap(long, default_value_t = 4)]
+    pub ffmpeg_threads: u32,
+
+    /// Don't add a silent audio track to the video.
+    /// Some streaming platforms refuse streams without an audio track, so it's added by default.
+    #[clap(long)]
+    pub ffmpeg_disable_audio: bool,
+
+    /// Additional arguments passed to ffmpeg, separated by whitespace, e.g. `--ffmpeg-extra-args="-tune zerolatency"`.
+    #[clap(long, allow_hyphen_values = true)]
+    pub ffmpeg_extra_args: Option<String>,
+
+    /// Enable periodical PNG snapshots of the canvas, which are written into the given directory.
+    #[clap(long)]
+    pub snapshot_directory: Option<String>,
+
+    /// Interval (in seconds) in which snapshots are taken.
+    #[clap(long, default_value = "60", value_parser = clap::value_parser!(u64).range(1..))]
+    pub snapshot_interval_s: u64,
+
+    /// Maximum number of snapshots kept in the snapshot directory. The oldest snapshots are deleted first.
+    #[clap(long)]
+    pub snapshot_max_files: Option<usize>,
+
+    /// Assemble all snapshots in the snapshot directory into a timelapse when breakwater is shut down.
+    #[clap(long, value_enum)]
+    pub snapshot_timelapse: Option<TimelapseFormat>,
+
+    /// Frames per second of the timelapse, every snapshot is one frame.
+    #[clap(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    pub snapshot_timelapse_fps: u32,
+
+    /// Enable a live view of the canvas for browsers on the given address, e.g. `[::]:8080`.
+    /// Serves an MJPEG stream on `/stream.mjpeg` and the current canvas on `/snapshot.png`.
+    #[clap(long)]
+    pub http_listen_address: Option<String>,
+
+    /// Frames per second of the MJPEG stream.
+    #[clap(long, default_value_t = 10, value_parser = clap::value_parser!(u32).range(1..=1000))]
+    pub http_fps: u32,
+
+    /// JPEG quality (1-100) of the MJPEG stream.
+    #[clap(long, default_value_t = 80, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub http_jpeg_quality: u8,
+
     /// Port of the VNC server.
     // #[cfg_attr(feature = "vnc", clap(short, long, default_value_t = 5900))]
     #[cfg(feature = "vnc")]
     #[clap(short, long, default_value_t = 5900)]
     pub vnc_port: u32,
 }
+
+/// Parses a resolution like `1280x720`
+fn parse_resolution(resolution: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid resolution {resolution}, expected something like 1280x720");
+    let (width, height) = resolution.split_once('x').ok_or_else(invalid)?;
+    Ok((
+        width.parse().map_err(|_| invalid())?,
+        height.parse().map_err(|_| invalid())?,
+    ))
+}
+
+/// Checks that all placeholders of a strftime pattern like `%Y-%m-%d` are valid, so that formatting can't fail later on
+fn parse_strftime_pattern(pattern: &str) -> Result<String, String> {
+    if StrftimeItems::new(pattern).any(|item| item == Item::Error) {
+        return Err(format!("Invalid strftime pattern {pattern}"));
+    }
+    Ok(pattern.to_string())
+}
+
+/// Checks the strftime pattern of the video files. Segments are named after the second they started in, so the pattern
+/// needs a placeholder for the seconds, otherwise every segment would overwrite the previous one.
+fn parse_video_file_pattern(pattern: &str) -> Result<String, String> {
+    let pattern = parse_strftime_pattern(pattern)?;
+    let contains_seconds = StrftimeItems::new(&pattern)
+        .any(|item| matches!(item, Item::Numeric(Numeric::Second | Numeric::Timestamp, _)));
+    if !contains_seconds {
+        return Err(format!(
+            "Video file pattern {pattern} needs to contain the seconds, e.g. using %S or %s"
+        ));
+    }
+    Ok(pattern)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("pixelflut_dump_%Y-%m-%d_%H-%M-%S.mp4", true)]
+    #[case("recording_%s.mkv", true)]
+    #[case("100%%.mp4", true)]
+    #[case("recording_%Q.mp4", false)]
+    #[case("recording_%.mp4", false)]
+    fn test_parse_strftime_pattern(#[case] pattern: &str, #[case] valid: bool) {
+        assert_eq!(parse_strftime_pattern(pattern).is_ok(), valid);
+    }
+
+    #[rstest]
+    #[case("pixelflut_dump_%Y-%m-%d_%H-%M-%S.mp4", true)]
+    #[case("recording_%s.mkv", true)]
+    #[case("recording_%F_%T.mkv", true)]
+    #[case("recording_%Y.mkv", false)]
+    #[case("recording_%Y-%m-%d_%H-%M.mkv", false)]
+    #[case("recording.mkv", false)]
+    #[case("recording_%Q_%S.mkv", false)]
+    fn test_parse_video_file_pattern(#[case] pattern: &str, #[case] valid: bool) {
+        assert_eq!(parse_video_file_pattern(pattern).is_ok(), valid);
+    }
+
+    #[rstest]
+    #[case("30", true)]
+    #[case("1", true)]
+    #[case("0", false)]
+    fn test_fps(#[case] fps: &str, #[case] valid: bool) {
+        assert_eq!(
+            Args::try_parse_from(["breakwater", "--fps", fps]).is_ok(),
+            valid
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use std::{fmt::Display, path::Path};
+
+use clap::ValueEnum;
+use image::{imageops::FilterType, io::Reader, DynamicImage, ImageError, RgbImage};
+
+use crate::framebuffer::FrameBuffer;
+
+/// How a background image, which does not have the size of the framebuffer, gets fitted onto it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum BackgroundImageScaling {
+    /// Scale the image keeping its aspect ratio, so that it covers the whole framebuffer, and crop whatever sticks out
+    #[default]
+    Fill,
+    /// Scale the image to exactly the size of the framebuffer, ignoring its aspect ratio
+    Stretch,
+    /// Don't scale at all, but cut the center out of the image. The image needs to be at least as big as the framebuffer
+    Crop,
+}
+
+#[derive(Debug)]
+pub enum BackgroundImageError {
+    /// The file could not be read or decoded, e.g. because it has an unsupported format
+    Image { path: String, source: ImageError },
+    /// The image is smaller than the framebuffer, so it can't be cropped
+    TooSmall {
+        path: String,
+        image_width: u32,
+        image_height: u32,
+        fb_width: usize,
+        fb_height: usize,
+    },
+}
+
+impl Display for BackgroundImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackgroundImageError::Image { path, source } => {
+                write!(f, "Failed to load background image {path}: {source}")
+            }
+            BackgroundImageError::TooSmall {
+                path,
+                image_width,
+                image_height,
+                fb_width,
+                fb_height,
+            } => write!(
+                f,
+                "Background image {path} has size {image_width}x{image_height}, which is too small to be cropped to {fb_width}x{fb_height}. Use a bigger image or scale it instead"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BackgroundImageError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BackgroundImageError::Image { source, .. } => Some(source),
+            BackgroundImageError::TooSmall { .. } => None,
+        }
+    }
+}
+
+/// Loads the PNG, JPEG or PPM image at `path`, fits it to the size of the framebuffer and draws it.
+/// The format is detected by looking at the contents of the file, not by its extension.
+pub fn draw_background_image(
+    fb: &FrameBuffer,
+    path: impl AsRef<Path>,
+    scaling: BackgroundImageScaling,
+) -> Result<(), BackgroundImageError> {
+    let path = path.as_ref();
+    let image_error = |source| BackgroundImageError::Image {
+        path: path.display().to_string(),
+        source,
+    };
+    let image = Reader::open(path)
+        .map_err(|err| image_error(err.into()))?
+        .with_guessed_format()
+        .map_err(|err| image_error(err.into()))?
+        .decode()
+        .map_err(image_error)?;
+
+    let image = fit_image(&image, fb.get_width(), fb.get_height(), scaling).ok_or_else(|| {
+        BackgroundImageError::TooSmall {
+            path: path.display().to_string(),
+            image_width: image.width(),
+            image_height: image.height(),
+            fb_width: fb.get_width(),
+            fb_height: fb.get_height(),
+        }
+    })?;
+
+    let pixels = image
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b] = pixel.0;
+            u32::from_le_bytes([r, g, b, 0])
+        })
+        .collect::<Vec<_>>();
+    fb.copy_from(&pixels);
+
+    Ok(())
+}
+
+/// Returns an image with exactly the given size, or [`None`] if the image is too small to be cropped.
+fn fit_image(
+    image: &DynamicImage,
+    width: usize,
+    height: usize,
+    scaling: BackgroundImageScaling,
+) -> Option<RgbImage> {
+    let (width, height) = (width as u32, height as u32);
+    if image.width() == width && image.height() == height {
+        return Some(image.to_rgb8());
+    }
+
+    let image = match scaling {
+        BackgroundImageScaling::Fill => image.resize_to_fill(width, height, FilterType::Triangle),
+        BackgroundImageScaling::Stretch => image.resize_exact(width, height, FilterType::Triangle),
+        BackgroundImageScaling::Crop => {
+            if image.width() < width || image.height() < height {
+                return None;
+            }
+            image.crop_imm(
+                (image.width() - width) / 2,
+                (image.height() - height) / 2,
+                width,
+                height,
+            )
+        }
+    };
+
+    Some(image.into_rgb8())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::Rgb;
+    use rstest::rstest;
+
+    fn image_file(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("breakwater_test_{}_{name}", std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Left half red, right half blue
+    fn test_image(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, _| {
+            if x < width / 2 {
+                Rgb([0xff, 0, 0])
+            } else {
+                Rgb([0, 0, 0xff])
+            }
+        })
+    }
+
+    #[rstest]
+    #[case("png", image::ImageFormat::Png)]
+    #[case("jpg", image::ImageFormat::Jpeg)]
+    #[case("ppm", image::ImageFormat::Pnm)]
+    fn test_draw_background_image(
+        #[case] extension: &str,
+        #[case] format: image::ImageFormat,
+        #[values(
+            BackgroundImageScaling::Fill,
+            BackgroundImageScaling::Stretch,
+            BackgroundImageScaling::Crop
+        )]
+        scaling: BackgroundImageScaling,
+    ) {
+        let path = image_file(&format!("background_{scaling:?}.{extension}"));
+        test_image(40, 20).save_with_format(&path, format).unwrap();
+
+        let fb = FrameBuffer::new(20, 10);
+        draw_background_image(&fb, &path, scaling).unwrap();
+
+        // JPEG is lossy, so only check the dominating color
+        let red = fb.get(1, 5).unwrap();
+        let blue = fb.get(18, 5).unwrap();
+        assert!(
+            red & 0xff > 0xe0 && red >> 16 < 0x20,
+            "{red:06x} is not red"
+        );
+        assert!(
+            blue >> 16 > 0xe0 && blue & 0xff < 0x20,
+            "{blue:06x} is not blue"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[rstest]
+    // Only stretching keeps the red stripe on the left, the other ones cut it off
+    #[case(BackgroundImageScaling::Fill, false)]
+    #[case(BackgroundImageScaling::Stretch, true)]
+    #[case(BackgroundImageScaling::Crop, false)]
+    fn test_scaling(#[case] scaling: BackgroundImageScaling, #[case] left_is_red: bool) {
+        let image = RgbImage::from_fn(100, 10, |x, _| {
+            if x < 10 {
+                Rgb([0xff, 0, 0])
+            } else {
+                Rgb([0, 0, 0xff])
+            }
+        });
+        let image = fit_image(&DynamicImage::ImageRgb8(image), 10, 10, scaling).unwrap();
+
+        assert_eq!(image.dimensions(), (10, 10));
+        let [r, _, b] = image.get_pixel(0, 5).0;
+        assert_eq!(r > b, left_is_red);
+    }
+
+    #[test]
+    fn test_crop_too_small_image() {
+        let path = image_file("background_too_small.png");
+        test_image(10, 10).save(&path).unwrap();
+
+        let fb = FrameBuffer::new(20, 10);
+        let err = draw_background_image(&fb, &path, BackgroundImageScaling::Crop).unwrap_err();
+        assert!(matches!(err, BackgroundImageError::TooSmall { .. }));
+        assert!(fb.snapshot().iter().all(|pixel| *pixel == 0));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_format() {
+        let path = image_file("background_unsupported.png");
+        std::fs::write(&path, b"PX 0 0 ffffff\n").unwrap();
+
+        let fb = FrameBuffer::new(20, 10);
+        let err = draw_background_image(&fb, &path, BackgroundImageScaling::Fill).unwrap_err();
+        assert!(matches!(err, BackgroundImageError::Image { .. }));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let fb = FrameBuffer::new(20, 10);
+        let err = draw_background_image(&fb, image_file("does_not_exist.png"), Default::default())
+            .unwrap_err();
+        assert!(err.to_string().contains("does_not_exist.png"));
+    }
+}
@@ -0,0 +1,228 @@
+use std::{
+    io::{Error, ErrorKind},
+    sync::Arc,
+    time::Duration,
+};
+
+use log::{info, warn};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex, time};
+
+use crate::framebuffer::FrameBuffer;
+
+/// Identifies canvas save files, followed by the width and height as little-endian u32 and the pixels as little-endian u32
+const CANVAS_SAVE_FILE_MAGIC: &[u8; 8] = b"BWCANVAS";
+const CANVAS_SAVE_FILE_HEADER_LENGTH: usize = CANVAS_SAVE_FILE_MAGIC.len() + 4 + 4;
+
+pub enum CanvasSaveMode {
+    Disabled,
+    Enabled { save_file: String, interval_s: u64 },
+}
+
+/// Periodically saves the canvas to disk, so that the artwork survives a restart of breakwater.
+pub struct CanvasPersistence {
+    fb: Arc<FrameBuffer>,
+    canvas_save_mode: CanvasSaveMode,
+    /// The periodic save and the one during shutdown must not write the temporary file at the same time
+    save_lock: Mutex<()>,
+}
+
+impl CanvasPersistence {
+    /// Restores the canvas from the save file, if there is one and it has the same dimensions as the framebuffer.
+    pub async fn new(fb: Arc<FrameBuffer>, canvas_save_mode: CanvasSaveMode) -> Self {
+        if let CanvasSaveMode::Enabled { save_file, .. } = &canvas_save_mode {
+            match load_from_file(&fb, save_file).await {
+                Ok(()) => info!("Restored canvas from {save_file}"),
+                Err(err) if err.kind() == ErrorKind::NotFound => (),
+                Err(err) => warn!("Not restoring canvas from {save_file}: {err}"),
+            }
+        }
+
+        CanvasPersistence {
+            fb,
+            canvas_save_mode,
+            save_lock: Mutex::new(()),
+        }
+    }
+
+    pub async fn run(&self) -> std::io::Result<()> {
+        let CanvasSaveMode::Enabled {
+            save_file,
+            interval_s,
+        } = &self.canvas_save_mode
+        else {
+            return Ok(());
+        };
+
+        let mut interval = time::interval(Duration::from_secs(*interval_s));
+        // The first tick completes immediately, there is nothing new to save yet
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            self.save(save_file).await?;
+        }
+    }
+
+    /// Saves the canvas one last time, so that nothing drawn since the last periodic save gets lost.
+    pub async fn shutdown(&self) -> std::io::Result<()> {
+        if let CanvasSaveMode::Enabled { save_file, .. } = &self.canvas_save_mode {
+            self.save(save_file).await?;
+            info!("Saved canvas to {save_file}");
+        }
+
+        Ok(())
+    }
+
+    async fn save(&self, save_file: &str) -> std::io::Result<()> {
+        let _save_lock = self.save_lock.lock().await;
+        save_to_file(&self.fb, save_file).await
+    }
+}
+
+/// Writes the visible canvas to `file_name`.
+/// The data is written to a temporary file first and renamed afterwards, so that a crash never leaves a truncated save file.
+pub async fn save_to_file(fb: &FrameBuffer, file_name: &str) -> std::io::Result<()> {
+    let mut bytes = Vec::with_capacity(CANVAS_SAVE_FILE_HEADER_LENGTH + fb.get_size() * 4);
+    bytes.extend_from_slice(CANVAS_SAVE_FILE_MAGIC);
+    bytes.extend_from_slice(&(fb.get_width() as u32).to_le_bytes());
+    bytes.extend_from_slice(&(fb.get_height() as u32).to_le_bytes());
+    bytes.extend(fb.snapshot().iter().flat_map(|pixel| pixel.to_le_bytes()));
+
+    let temporary_file_name = format!("{file_name}.tmp");
+    let mut temporary_file = fs::File::create(&temporary_file_name).await?;
+    temporary_file.write_all(&bytes).await?;
+    // Otherwise the rename can hit the disk before the data, leaving an empty save file after a power loss
+    temporary_file.sync_all().await?;
+    drop(temporary_file);
+    fs::rename(&temporary_file_name, file_name).await
+}
+
+/// Reads the canvas from `file_name` into the framebuffer.
+/// Fails without touching the framebuffer if the file is invalid or its dimensions don't match the framebuffer.
+pub async fn load_from_file(fb: &FrameBuffer, file_name: &str) -> std::io::Result<()> {
+    let bytes = fs::read(file_name).await?;
+
+    let (header, pixels) = bytes
+        .split_at_checked(CANVAS_SAVE_FILE_HEADER_LENGTH)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "File too short"))?;
+    let (magic, dimensions) = header.split_at(CANVAS_SAVE_FILE_MAGIC.len());
+    if magic != CANVAS_SAVE_FILE_MAGIC {
+        return Err(Error::new(ErrorKind::InvalidData, "Not a canvas save file"));
+    }
+
+    let width = u32::from_le_bytes(dimensions[0..4].try_into().unwrap()) as usize;
+    let height = u32::from_le_bytes(dimensions[4..8].try_into().unwrap()) as usize;
+    if width != fb.get_width() || height != fb.get_height() {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Saved canvas has size {width}x{height}, but the framebuffer has size {}x{}",
+                fb.get_width(),
+                fb.get_height()
+            ),
+        ));
+    }
+    if pixels.len() != width * height * 4 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "File has the wrong length",
+        ));
+    }
+
+    let pixels = pixels
+        .chunks_exact(4)
+        .map(|pixel| u32::from_le_bytes(pixel.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    fb.copy_from(&pixels);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    fn save_file(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("breakwater_test_{}_{name}", std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_save_and_load(#[values(false, true)] padded: bool) {
+        let save_file = save_file(&format!("save_and_load_{padded}"));
+        let fb = if padded {
+            FrameBuffer::new_padded(30, 20)
+        } else {
+            FrameBuffer::new(30, 20)
+        };
+        fb.fill_rect(5, 5, 10, 10, 0x123456);
+        fb.set(29, 19, 0xabcdef);
+        save_to_file(&fb, &save_file).await.unwrap();
+
+        let restored = FrameBuffer::new(30, 20);
+        load_from_file(&restored, &save_file).await.unwrap();
+        assert_eq!(fb.snapshot(), restored.snapshot());
+
+        fs::remove_file(&save_file).await.unwrap();
+    }
+
+    #[rstest]
+    #[case(FrameBuffer::new(31, 20))]
+    #[case(FrameBuffer::new(30, 21))]
+    #[case(FrameBuffer::new(20, 30))]
+    #[tokio::test]
+    async fn test_load_with_different_size(#[case] fb: FrameBuffer) {
+        let save_file = save_file(&format!(
+            "different_size_{}x{}",
+            fb.get_width(),
+            fb.get_height()
+        ));
+        let saved = FrameBuffer::new(30, 20);
+        saved.fill_rect(0, 0, 30, 20, 0x123456);
+        save_to_file(&saved, &save_file).await.unwrap();
+
+        let err = load_from_file(&fb, &save_file).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(fb.snapshot().iter().all(|pixel| *pixel == 0));
+
+        fs::remove_file(&save_file).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_save_on_shutdown() {
+        let save_file = save_file("save_on_shutdown");
+        let fb = Arc::new(FrameBuffer::new(30, 20));
+        let canvas_persistence = CanvasPersistence::new(
+            Arc::clone(&fb),
+            CanvasSaveMode::Enabled {
+                save_file: save_file.clone(),
+                interval_s: 3600,
+            },
+        )
+        .await;
+
+        fb.set(1, 2, 0x123456);
+        canvas_persistence.shutdown().await.unwrap();
+
+        let restored = FrameBuffer::new(30, 20);
+        load_from_file(&restored, &save_file).await.unwrap();
+        assert_eq!(restored.get(1, 2), Some(0x123456));
+
+        fs::remove_file(&save_file).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_invalid_file() {
+        let save_file = save_file("invalid_file");
+        fs::write(&save_file, b"PX 0 0 ffffff\n").await.unwrap();
+
+        let fb = FrameBuffer::new(30, 20);
+        let err = load_from_file(&fb, &save_file).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        fs::remove_file(&save_file).await.unwrap();
+    }
+}
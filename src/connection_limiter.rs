@@ -0,0 +1,248 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::{IpAddr, Ipv6Addr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Limits the number of concurrent connections per IP address and per IPv6 prefix.
+///
+/// The limits are enforced when accepting connections, so we keep our own counters instead of relying on the
+/// connections tracked by [`crate::statistics::Statistics`], which lag behind.
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    max_connections_per_ip: Option<u32>,
+    max_connections_per_ipv6_prefix: Option<u32>,
+    ipv6_prefix_length: u8,
+
+    connections: Mutex<Connections>,
+    /// Counted here instead of via the statistics channel, so that no rejection gets lost during a flood
+    rejected_connections: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Connections {
+    /// Only contains IPs with at least one connection
+    for_ip: HashMap<IpAddr, u32>,
+    /// Only contains IPv6 prefixes with at least one connection, the prefix is stored as the masked address
+    for_ipv6_prefix: HashMap<Ipv6Addr, u32>,
+}
+
+/// Releases the connection slot when dropped, so it should be kept as long as the connection is open.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl ConnectionLimiter {
+    /// Creates a new limiter, [`None`] disables the corresponding limit.
+    /// The IP needs to be canonical, so that IPv4 addresses mapped into IPv6 are limited as IPv4 addresses.
+    pub fn new(
+        max_connections_per_ip: Option<u32>,
+        max_connections_per_ipv6_prefix: Option<u32>,
+        ipv6_prefix_length: u8,
+    ) -> Self {
+        assert!(
+            ipv6_prefix_length <= 128,
+            "IPv6 prefix length must be at most 128"
+        );
+        ConnectionLimiter {
+            max_connections_per_ip,
+            max_connections_per_ipv6_prefix,
+            ipv6_prefix_length,
+            connections: Mutex::default(),
+            rejected_connections: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns a limiter that accepts all connections.
+    pub fn unlimited() -> Self {
+        ConnectionLimiter::new(None, None, 64)
+    }
+
+    /// Reserves a connection slot for the IP.
+    /// Returns [`None`] if the IP (or its IPv6 prefix) already has the maximum number of connections.
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut connections = self
+            .connections
+            .lock()
+            .expect("Connection limiter poisoned");
+
+        let connections_for_ip = connections.for_ip.get(&ip).copied().unwrap_or(0);
+        if self
+            .max_connections_per_ip
+            .is_some_and(|max| connections_for_ip >= max)
+        {
+            return None;
+        }
+
+        let prefix = self.ipv6_prefix(ip);
+        if let Some(prefix) = prefix {
+            let connections_for_prefix = connections
+                .for_ipv6_prefix
+                .get(&prefix)
+                .copied()
+                .unwrap_or(0);
+            if self
+                .max_connections_per_ipv6_prefix
+                .is_some_and(|max| connections_for_prefix >= max)
+            {
+                return None;
+            }
+            *connections.for_ipv6_prefix.entry(prefix).or_insert(0) += 1;
+        }
+        *connections.for_ip.entry(ip).or_insert(0) += 1;
+
+        Some(ConnectionGuard {
+            limiter: Arc::clone(self),
+            ip,
+        })
+    }
+
+    /// Returns the number of connections currently open from the IP.
+    pub fn connections_for_ip(&self, ip: IpAddr) -> u32 {
+        self.connections
+            .lock()
+            .expect("Connection limiter poisoned")
+            .for_ip
+            .get(&ip)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Counts a connection that was rejected for any reason, e.g. by [`ConnectionLimiter::try_acquire`] or the access list.
+    pub fn count_rejected_connection(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of connections rejected since the start.
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut connections = self
+            .connections
+            .lock()
+            .expect("Connection limiter poisoned");
+        decrement(&mut connections.for_ip, ip);
+        if let Some(prefix) = self.ipv6_prefix(ip) {
+            decrement(&mut connections.for_ipv6_prefix, prefix);
+        }
+    }
+
+    fn ipv6_prefix(&self, ip: IpAddr) -> Option<Ipv6Addr> {
+        match ip {
+            IpAddr::V4(_) => None,
+            IpAddr::V6(v6) => Some(ipv6_prefix(v6, self.ipv6_prefix_length)),
+        }
+    }
+}
+
+/// Returns the prefix of the given length as masked address, e.g. `2001:db8::` for `2001:db8::1/64`.
+pub(crate) fn ipv6_prefix(ip: Ipv6Addr, prefix_length: u8) -> Ipv6Addr {
+    let mask = u128::MAX
+        .checked_shl(128 - prefix_length as u32)
+        .unwrap_or(0);
+    Ipv6Addr::from(u128::from(ip) & mask)
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+/// Decrements the counter and removes it once it reaches zero, so that the maps don't grow forever
+fn decrement<K: Eq + std::hash::Hash>(counters: &mut HashMap<K, u32>, key: K) {
+    if let Entry::Occupied(mut entry) = counters.entry(key) {
+        *entry.get_mut() -= 1;
+        if *entry.get() == 0 {
+            entry.remove();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_limit_per_ip() {
+        let limiter = Arc::new(ConnectionLimiter::new(Some(2), None, 64));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other_ip: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let first = limiter.try_acquire(ip).unwrap();
+        let _second = limiter.try_acquire(ip).unwrap();
+        assert!(limiter.try_acquire(ip).is_none());
+        assert!(limiter.try_acquire(other_ip).is_some());
+        assert_eq!(limiter.connections_for_ip(ip), 2);
+
+        // Closing a connection frees up a slot
+        drop(first);
+        assert_eq!(limiter.connections_for_ip(ip), 1);
+        assert!(limiter.try_acquire(ip).is_some());
+    }
+
+    #[rstest]
+    // Same /64
+    #[case("2001:db8::1", "2001:db8::2", 64, false)]
+    #[case("2001:db8:0:0:ffff::1", "2001:db8::2", 64, false)]
+    // Different /64
+    #[case("2001:db8:0:1::1", "2001:db8::2", 64, true)]
+    // Different /64, but same /48
+    #[case("2001:db8:0:1::1", "2001:db8::2", 48, false)]
+    #[case("2001:db8::1", "2001:db8::2", 128, true)]
+    #[case("2001:db8::1", "fe80::1", 0, false)]
+    fn test_limit_per_ipv6_prefix(
+        #[case] first_ip: IpAddr,
+        #[case] second_ip: IpAddr,
+        #[case] ipv6_prefix_length: u8,
+        #[case] expected_accepted: bool,
+    ) {
+        let limiter = Arc::new(ConnectionLimiter::new(None, Some(1), ipv6_prefix_length));
+
+        let _first = limiter.try_acquire(first_ip).unwrap();
+        assert_eq!(limiter.try_acquire(second_ip).is_some(), expected_accepted);
+    }
+
+    #[test]
+    fn test_ipv4_is_not_limited_by_ipv6_prefix() {
+        let limiter = Arc::new(ConnectionLimiter::new(None, Some(1), 0));
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let _first = limiter.try_acquire(ip).unwrap();
+        assert!(limiter.try_acquire(ip).is_some());
+    }
+
+    #[test]
+    fn test_released_when_rejected_by_prefix() {
+        let limiter = Arc::new(ConnectionLimiter::new(Some(5), Some(1), 64));
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+
+        let first = limiter.try_acquire(ip).unwrap();
+        assert!(limiter.try_acquire(ip).is_none());
+        // The rejected connection must not have been counted
+        assert_eq!(limiter.connections_for_ip(ip), 1);
+        drop(first);
+        assert_eq!(limiter.connections_for_ip(ip), 0);
+        assert!(limiter.try_acquire(ip).is_some());
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let limiter = Arc::new(ConnectionLimiter::unlimited());
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let guards = (0..1000)
+            .map(|_| limiter.try_acquire(ip).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(limiter.connections_for_ip(ip), 1000);
+        drop(guards);
+        assert_eq!(limiter.connections_for_ip(ip), 0);
+    }
+}
@@ -1,8 +1,15 @@
+pub mod access_list;
 pub mod args;
+pub mod background_image;
+pub mod canvas_persistence;
+pub mod connection_limiter;
 pub mod framebuffer;
 pub mod network;
 pub mod parser;
 pub mod prometheus_exporter;
+pub mod proxy_protocol;
+pub mod rate_limiter;
 pub mod sinks;
 pub mod statistics;
 pub mod test;
+pub mod websocket;
@@ -1,33 +1,47 @@
 #[cfg(feature = "vnc")]
 use breakwater::{
+    access_list::AccessList,
     args::Args,
+    background_image::draw_background_image,
+    canvas_persistence::{CanvasPersistence, CanvasSaveMode},
+    connection_limiter::ConnectionLimiter,
     framebuffer::FrameBuffer,
-    network::Network,
+    network::{ConnectionPolicy, Network, UdpNetwork},
     prometheus_exporter::PrometheusExporter,
-    sinks::{ffmpeg::FfmpegSink, vnc::VncServer},
+    rate_limiter::RateLimiter,
+    sinks::{SinkContext, SinkRegistry},
     statistics::{Statistics, StatisticsEvent, StatisticsInformationEvent, StatisticsSaveMode},
+    websocket::WebSocketNetwork,
 };
 use clap::Parser;
 use env_logger::Env;
-use std::sync::Arc;
-#[cfg(feature = "vnc")]
-use thread_priority::{ThreadBuilderExt, ThreadPriority};
-use tokio::sync::{broadcast, mpsc};
+use log::info;
+use std::{path::Path, sync::Arc};
+use tokio::{
+    signal,
+    sync::{broadcast, mpsc},
+};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
     let args = Args::parse();
 
-    let fb = Arc::new(FrameBuffer::new(args.width, args.height));
+    let fb = if args.padded_framebuffer {
+        Arc::new(FrameBuffer::new_padded(args.width, args.height))
+    } else {
+        Arc::new(FrameBuffer::new(args.width, args.height))
+    };
+
+    if let Some(background_image) = &args.background_image {
+        draw_background_image(&fb, background_image, args.background_image_scaling)?;
+    }
 
     // If we make the channel to big, stats will start to lag behind
     // TODO: Check performance impact in real-world scenario. Maybe the statistics thread blocks the other threads
     let (statistics_tx, statistics_rx) = mpsc::channel::<StatisticsEvent>(100);
     let (statistics_information_tx, statistics_information_rx_for_prometheus_exporter) =
         broadcast::channel::<StatisticsInformationEvent>(2);
-    #[cfg(feature = "vnc")]
-    let statistics_information_rx_for_vnc_server = statistics_information_tx.subscribe();
 
     let statistics_save_mode = if args.disable_statistics_save_file {
         StatisticsSaveMode::Disabled
@@ -39,44 +53,114 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     };
     let mut statistics = Statistics::new(
         statistics_rx,
-        statistics_information_tx,
+        statistics_information_tx.clone(),
         statistics_save_mode,
     )?;
 
-    let network = Network::new(&args.listen_address, Arc::clone(&fb), statistics_tx.clone());
+    let canvas_save_mode = if args.disable_canvas_save_file {
+        CanvasSaveMode::Disabled
+    } else {
+        CanvasSaveMode::Enabled {
+            save_file: args.canvas_save_file.clone(),
+            interval_s: args.canvas_save_interval_s,
+        }
+    };
+    // Restores the canvas, so we need to do this before clients can connect
+    let canvas_persistence =
+        Arc::new(CanvasPersistence::new(Arc::clone(&fb), canvas_save_mode).await);
+    let periodic_canvas_persistence = Arc::clone(&canvas_persistence);
+    let canvas_persistence_thread = tokio::spawn(async move {
+        periodic_canvas_persistence
+            .run()
+            .await
+            .expect("Canvas persistence thread failed")
+    });
+
+    // Shared by all listeners, so that clients can't get around the limit by using another protocol
+    let connection_limiter = Arc::new(ConnectionLimiter::new(
+        args.max_connections_per_ip,
+        args.max_connections_per_ipv6_prefix,
+        args.ipv6_prefix_length,
+    ));
+    let rate_limiter = Arc::new(RateLimiter::new(
+        args.rate_limit_unit,
+        args.rate_limit,
+        args.ipv6_prefix_length,
+    ));
+    if let Some(rate_limit_file) = args.rate_limit_file.clone() {
+        let rate_limiter = Arc::clone(&rate_limiter);
+        tokio::spawn(async move {
+            rate_limiter
+                .follow_limit_file(Path::new(&rate_limit_file))
+                .await
+        });
+    }
+    let access_list = match &args.access_list_file {
+        Some(access_list_file) => {
+            let access_list = Arc::new(AccessList::load(Path::new(access_list_file)).await?);
+            let following_access_list = Arc::clone(&access_list);
+            let access_list_file = access_list_file.clone();
+            tokio::spawn(async move {
+                following_access_list
+                    .follow_file(Path::new(&access_list_file))
+                    .await
+            });
+            access_list
+        }
+        None => Arc::new(AccessList::allow_all()),
+    };
+    let policy = ConnectionPolicy {
+        max_rect_area: args.max_rect_area,
+        parser_implementation: args.parser,
+        connection_limiter: Arc::clone(&connection_limiter),
+        rate_limiter,
+        access_list,
+    };
+    let network = Network::new(
+        &args.listen_address,
+        Arc::clone(&fb),
+        statistics_tx.clone(),
+        policy.clone(),
+        args.proxy_protocol,
+    );
     let network_listener_thread = tokio::spawn(async move {
         network.listen().await.unwrap();
     });
 
-    let ffmpeg_sink = FfmpegSink::new(&args, Arc::clone(&fb));
-    let ffmpeg_thread =
-        ffmpeg_sink.map(|sink| tokio::spawn(async move { sink.run().await.unwrap() }));
-
-    #[cfg(feature = "vnc")]
-    let vnc_server_thread = {
-        let fb_for_vnc_server = Arc::clone(&fb);
-        // TODO Use tokio::spawn instead of std::thread::spawn
-        // I was not able to get to work with async closure
-        // We than also need to think about setting a priority
-        std::thread::Builder::new()
-        .name("breakwater vnc server thread".to_owned())
-        .spawn_with_priority(
-            ThreadPriority::Crossplatform(70.try_into().expect("Failed to get cross-platform ThreadPriority. Please report this error message together with your operating system.")),
-            move |_| {
-                let mut vnc_server = VncServer::new(
-                    fb_for_vnc_server,
-                    args.vnc_port,
-                    args.fps,
-                    statistics_tx,
-                    statistics_information_rx_for_vnc_server,
-                    &args.text,
-                    &args.font,
-                );
-                vnc_server.run();
-            },
-        )
-        .unwrap()
+    let udp_listener_thread = args.udp_listen_address.as_ref().map(|listen_address| {
+        let udp_network = UdpNetwork::new(
+            listen_address,
+            Arc::clone(&fb),
+            statistics_tx.clone(),
+            policy.clone(),
+            args.udp_respond,
+        );
+        tokio::spawn(async move {
+            udp_network.listen().await.unwrap();
+        })
+    });
+
+    let websocket_listener_thread = args
+        .websocket_listen_address
+        .as_ref()
+        .map(|listen_address| {
+            let websocket_network = WebSocketNetwork::new(
+                listen_address,
+                Arc::clone(&fb),
+                statistics_tx.clone(),
+                policy.clone(),
+            );
+            tokio::spawn(async move {
+                websocket_network.listen().await.unwrap();
+            })
+        });
+
+    let sink_context = SinkContext {
+        fb: Arc::clone(&fb),
+        statistics_tx,
+        statistics_information_tx,
     };
+    let mut sinks = SinkRegistry::with_builtin_sinks().start(&args, &sink_context)?;
 
     let statistics_thread =
         tokio::spawn(async move { statistics.start().await.expect("Statistics thread failed") });
@@ -84,23 +168,51 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let mut prometheus_exporter = PrometheusExporter::new(
         &args.prometheus_listen_address,
         statistics_information_rx_for_prometheus_exporter,
+        Arc::clone(&connection_limiter),
     );
     let prometheus_exporter_thread = tokio::spawn(async move {
         prometheus_exporter.run().await;
     });
 
-    prometheus_exporter_thread.await?;
-    network_listener_thread.await?;
-    if let Some(ffmpeg_thread) = ffmpeg_thread {
-        ffmpeg_thread.await?;
-    }
-    statistics_thread.await?;
-    #[cfg(feature = "vnc")]
-    {
-        vnc_server_thread
-            .join()
-            .expect("Failed to join VNC server thread");
+    let threads = async {
+        prometheus_exporter_thread.await?;
+        network_listener_thread.await?;
+        if let Some(udp_listener_thread) = udp_listener_thread {
+            udp_listener_thread.await?;
+        }
+        if let Some(websocket_listener_thread) = websocket_listener_thread {
+            websocket_listener_thread.await?;
+        }
+        sinks.join().await?;
+        statistics_thread.await?;
+        canvas_persistence_thread.await?;
+        Ok::<_, Box<dyn std::error::Error>>(())
+    };
+
+    tokio::select! {
+        result = threads => result?,
+        _ = shutdown_signal() => info!("Shutting down"),
     }
 
+    sinks.shutdown().await?;
+    canvas_persistence.shutdown().await?;
+
     Ok(())
 }
+
+/// Completes once the user pressed Ctrl+C or - e.g. when running in a container - SIGTERM was received.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = signal::ctrl_c() => (),
+            _ = sigterm.recv() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c()
+        .await
+        .expect("Failed to install Ctrl+C handler");
+}
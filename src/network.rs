@@ -1,69 +1,496 @@
 use crate::{
+    access_list::AccessList,
+    connection_limiter::ConnectionLimiter,
     framebuffer::FrameBuffer,
-    parser::{parse_pixelflut_commands, ParserState, PARSER_LOOKAHEAD},
+    parser::{
+        original::OriginalParser, reference::ReferenceParser, Parser, ParserImplementation,
+        BINARY_PIXEL_COMMAND_LENGTH, PARSER_LOOKAHEAD,
+    },
+    proxy_protocol::{read_proxy_header, PROXY_HEADER_TIMEOUT},
+    rate_limiter::{RateLimitBucket, RateLimitUnit, RateLimiter},
     statistics::StatisticsEvent,
 };
-use log::{debug, info};
+use futures_util::future::try_join_all;
+use log::{debug, info, trace};
 use std::{
-    cmp::min,
+    cmp::{max, min},
+    collections::HashMap,
+    future::Future,
     net::{IpAddr, Ipv4Addr},
+    pin::Pin,
     sync::Arc,
     time::Duration,
 };
+#[cfg(unix)]
+use tokio::net::UnixListener;
 use tokio::{
     io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpListener,
+    net::{TcpListener, UdpSocket},
     sync::mpsc::Sender,
-    time::Instant,
+    time::{self, timeout, Instant, MissedTickBehavior},
 };
 
 const NETWORK_BUFFER_SIZE: usize = 256_000;
+/// Rate limited connections read at least this much, so that they can always make progress
+const MIN_RATE_LIMITED_READ_SIZE: usize = PARSER_LOOKAHEAD;
 // Every client connection spawns a new thread, so we need to limit the number of stat events we send
 const STATISTICS_REPORT_INTERVAL: Duration = Duration::from_millis(250);
+/// The length field of the UDP header is 16 bit, so no datagram can carry more than this
+const UDP_MAX_PAYLOAD_SIZE: usize = 65_535;
+/// Maximum payload of an UDP datagram that can be sent over IPv4 as well, as the IPv4 header takes up some of the length
+const UDP_MAX_RESPONSE_SIZE: usize = 65_507;
+/// Listen addresses starting with this are Unix domain sockets
+pub const UNIX_SOCKET_PREFIX: &str = "unix:";
+/// Unix domain sockets have no IP, so their clients show up as localhost in the statistics.
+/// They are protected by the permissions of the socket file instead, so they bypass the access list and all limits.
+const UNIX_SOCKET_CLIENT_IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
 
+/// How clients are parsed and limited. Shared by all listeners, so that clients can't get around the limits by using
+/// another protocol.
+#[derive(Clone, Debug)]
+pub struct ConnectionPolicy {
+    pub max_rect_area: usize,
+    pub parser_implementation: ParserImplementation,
+    /// Not used for UDP, as it has no connections
+    pub connection_limiter: Arc<ConnectionLimiter>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub access_list: Arc<AccessList>,
+}
+
+impl ConnectionPolicy {
+    /// Returns a policy that allows everyone without any limits.
+    pub fn unlimited(max_rect_area: usize, parser_implementation: ParserImplementation) -> Self {
+        ConnectionPolicy {
+            max_rect_area,
+            parser_implementation,
+            connection_limiter: Arc::new(ConnectionLimiter::unlimited()),
+            rate_limiter: Arc::new(RateLimiter::unlimited()),
+            access_list: Arc::new(AccessList::allow_all()),
+        }
+    }
+}
+
+/// Pixelflut server accepting TCP connections, optionally on multiple addresses and a Unix domain socket.
 pub struct Network {
-    listen_address: String,
+    /// TCP addresses such as `[::]:1234`, or Unix domain sockets as `unix:/path/to/socket`
+    listen_addresses: Vec<String>,
     fb: Arc<FrameBuffer>,
     statistics_tx: Sender<StatisticsEvent>,
+    policy: ConnectionPolicy,
+    /// Expect a PROXY protocol header on every connection and use the client address from it
+    proxy_protocol: bool,
 }
 
 impl Network {
     pub fn new(
-        listen_address: &str,
+        listen_addresses: &[String],
         fb: Arc<FrameBuffer>,
         statistics_tx: Sender<StatisticsEvent>,
+        policy: ConnectionPolicy,
+        proxy_protocol: bool,
     ) -> Self {
         Network {
-            listen_address: listen_address.to_string(),
+            listen_addresses: listen_addresses.to_vec(),
             fb,
             statistics_tx,
+            policy,
+            proxy_protocol,
         }
     }
 
+    /// Binds all listen addresses and serves them until one of them fails.
     pub async fn listen(&self) -> tokio::io::Result<()> {
-        let listener = TcpListener::bind(&self.listen_address).await?;
-        info!("Started Pixelflut server on {}", self.listen_address);
+        // We bind everything upfront, so that a wrong address is noticed right away
+        let mut servers: Vec<Pin<Box<dyn Future<Output = tokio::io::Result<()>> + Send + '_>>> =
+            Vec::new();
+        // Dropped together with the servers, e.g. when the runtime shuts down
+        let mut unix_socket_files = Vec::new();
+        for listen_address in &self.listen_addresses {
+            if let Some(path) = listen_address.strip_prefix(UNIX_SOCKET_PREFIX) {
+                let listener = bind_unix_socket(path)?;
+                unix_socket_files.push(UnixSocketFile(path.to_string()));
+                servers.push(Box::pin(self.serve_unix(listener)));
+            } else {
+                let listener = TcpListener::bind(listen_address).await?;
+                servers.push(Box::pin(self.serve(listener)));
+            }
+            info!("Started Pixelflut server on {listen_address}");
+        }
 
+        try_join_all(servers).await?;
+        Ok(())
+    }
+
+    pub async fn serve(&self, listener: TcpListener) -> tokio::io::Result<()> {
         loop {
             let (socket, socket_addr) = listener.accept().await?;
             // If you connect via IPv4 you often show up as embedded inside an IPv6 address
             // Extracting the embedded information here, so we get the real (TM) address
-            let ip = ip_to_canonical(socket_addr.ip());
+            self.spawn_connection(socket, Some(ip_to_canonical(socket_addr.ip())));
+        }
+    }
 
-            let fb_for_thread = Arc::clone(&self.fb);
-            let statistics_tx_for_thread = self.statistics_tx.clone();
-            tokio::spawn(async move {
-                handle_connection(socket, ip, fb_for_thread, statistics_tx_for_thread).await;
-            });
+    #[cfg(unix)]
+    pub async fn serve_unix(&self, listener: UnixListener) -> tokio::io::Result<()> {
+        loop {
+            let (socket, _) = listener.accept().await?;
+            self.spawn_connection(socket, None);
         }
     }
+
+    #[cfg(not(unix))]
+    pub async fn serve_unix(&self, listener: std::convert::Infallible) -> tokio::io::Result<()> {
+        match listener {}
+    }
+
+    /// Handles the connection of the given peer, which has no IP if it connected over a Unix domain socket.
+    fn spawn_connection(
+        &self,
+        mut socket: impl AsyncReadExt + AsyncWriteExt + Send + Unpin + 'static,
+        peer_ip: Option<IpAddr>,
+    ) {
+        let fb = Arc::clone(&self.fb);
+        let statistics_tx = self.statistics_tx.clone();
+        let policy = self.policy.clone();
+        let proxy_protocol = self.proxy_protocol;
+        // Everything that might need to wait for the client happens in the spawned task, so that slow clients can't
+        // hold up the accept loop
+        tokio::spawn(async move {
+            let ip = if proxy_protocol {
+                match timeout(PROXY_HEADER_TIMEOUT, read_proxy_header(&mut socket)).await {
+                    Ok(Ok(client_ip)) => client_ip.map(ip_to_canonical).or(peer_ip),
+                    Ok(Err(err)) => {
+                        debug!(
+                            "Invalid PROXY protocol header from {}: {err}",
+                            describe_peer(peer_ip)
+                        );
+                        return;
+                    }
+                    Err(_) => {
+                        debug!(
+                            "No PROXY protocol header from {} in time",
+                            describe_peer(peer_ip)
+                        );
+                        return;
+                    }
+                }
+            } else {
+                peer_ip
+            };
+
+            let Some(ip) = ip else {
+                // A local client on a Unix domain socket, which are protected by their file permissions instead
+                handle_connection_with_parser(
+                    socket,
+                    UNIX_SOCKET_CLIENT_IP,
+                    fb,
+                    statistics_tx,
+                    &Arc::new(RateLimiter::unlimited()),
+                    policy.max_rect_area,
+                    policy.parser_implementation,
+                )
+                .await;
+                return;
+            };
+
+            if !policy.access_list.is_allowed(ip) {
+                reject_connection(
+                    ip,
+                    "it is denied by the access list",
+                    &policy.connection_limiter,
+                );
+                return;
+            }
+            let Some(connection_guard) = policy.connection_limiter.try_acquire(ip) else {
+                reject_connection(
+                    ip,
+                    "it has too many connections",
+                    &policy.connection_limiter,
+                );
+                return;
+            };
+
+            // Keeps the connection slot until the connection is closed
+            let _connection_guard = connection_guard;
+            let connection = handle_connection_with_parser(
+                socket,
+                ip,
+                fb,
+                statistics_tx.clone(),
+                &policy.rate_limiter,
+                policy.max_rect_area,
+                policy.parser_implementation,
+            );
+            close_when_denied(connection, ip, &policy.access_list, &statistics_tx).await
+        });
+    }
 }
 
-pub async fn handle_connection(
-    mut stream: impl AsyncReadExt + AsyncWriteExt + Unpin,
+fn describe_peer(peer_ip: Option<IpAddr>) -> String {
+    match peer_ip {
+        Some(ip) => ip.to_string(),
+        None => "Unix domain socket".to_string(),
+    }
+}
+
+/// Removes the Unix domain socket file once dropped, so that we don't leave a stale socket behind on shutdown.
+struct UnixSocketFile(String);
+
+impl Drop for UnixSocketFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.0) {
+            debug!("Failed to remove Unix domain socket {}: {err}", self.0);
+        }
+    }
+}
+
+/// Binds the Unix domain socket, replacing a stale socket left over from a previous run.
+#[cfg(unix)]
+fn bind_unix_socket(path: &str) -> tokio::io::Result<UnixListener> {
+    use std::os::unix::fs::FileTypeExt;
+
+    // Only remove sockets, we don't want to delete a file because of a typo in the path
+    if std::fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+#[cfg(not(unix))]
+fn bind_unix_socket(_path: &str) -> tokio::io::Result<std::convert::Infallible> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "Unix domain sockets are only supported on Unix",
+    ))
+}
+
+/// Pixelflut server for clients sending UDP datagrams, so that they don't have any connection overhead.
+///
+/// Every datagram needs to contain complete commands. It's parsed with a fresh parser, so e.g. an OFFSET only applies to
+/// the datagram it was sent in.
+///
+/// As there is no connection we could stop reading from, clients exceeding the rate limit get their datagrams dropped
+/// until they have paid back what they used. The same goes for datagrams of clients denied by the access list.
+pub struct UdpNetwork {
+    listen_address: String,
+    fb: Arc<FrameBuffer>,
+    statistics_tx: Sender<StatisticsEvent>,
+    policy: ConnectionPolicy,
+    /// Send responses (e.g. to `PX x y`) back to the source address.
+    /// As the source address can be spoofed, this allows reflection attacks, so it's disabled by default.
+    respond: bool,
+}
+
+impl UdpNetwork {
+    pub fn new(
+        listen_address: &str,
+        fb: Arc<FrameBuffer>,
+        statistics_tx: Sender<StatisticsEvent>,
+        policy: ConnectionPolicy,
+        respond: bool,
+    ) -> Self {
+        UdpNetwork {
+            listen_address: listen_address.to_string(),
+            fb,
+            statistics_tx,
+            policy,
+            respond,
+        }
+    }
+
+    pub async fn listen(&self) -> tokio::io::Result<()> {
+        let socket = UdpSocket::bind(&self.listen_address).await?;
+        info!("Started Pixelflut UDP server on {}", self.listen_address);
+
+        self.serve(socket).await
+    }
+
+    pub async fn serve(&self, socket: UdpSocket) -> tokio::io::Result<()> {
+        // One additional byte for the newline we might need to append
+        let mut buffer = vec![0u8; UDP_MAX_PAYLOAD_SIZE + 1 + PARSER_LOOKAHEAD];
+        let mut responses = Vec::new();
+
+        // Same as for TCP connections we bulk the statistics and send them pre-aggregated.
+        // They are sent on a timer, so that they don't get stuck when no further datagrams arrive.
+        let mut statistics_interval = time::interval(STATISTICS_REPORT_INTERVAL);
+        statistics_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut statistics_bytes_for_ip: HashMap<IpAddr, u64> = HashMap::new();
+        let mut statistics_pixels_for_ip: HashMap<IpAddr, u64> = HashMap::new();
+        // The buckets are shared with the connections of the client and dropped once they are full again
+        let mut rate_limit_buckets: HashMap<IpAddr, RateLimitBucket> = HashMap::new();
+
+        loop {
+            tokio::select! {
+                received = socket.recv_from(&mut buffer[..UDP_MAX_PAYLOAD_SIZE]) => {
+                    let (bytes_read, source) = received?;
+                    let ip = ip_to_canonical(source.ip());
+                    if !self.policy.access_list.is_allowed(ip) {
+                        trace!("Dropping UDP datagram from {ip}, as it is denied by the access list");
+                        continue;
+                    }
+                    *statistics_bytes_for_ip.entry(ip).or_insert(0) += bytes_read as u64;
+
+                    let rate_limit_bucket = rate_limit_buckets
+                        .entry(ip)
+                        .or_insert_with(|| self.policy.rate_limiter.bucket(ip));
+                    if rate_limit_bucket.available() == Some(0) {
+                        trace!("Dropping UDP datagram from {ip}, as it exceeded the rate limit");
+                        continue;
+                    }
+
+                    // The datagram contains complete commands, so the last one is allowed to omit the newline
+                    let mut data_end = bytes_read;
+                    if bytes_read > 0 && buffer[bytes_read - 1] != b'\n' {
+                        buffer[data_end] = b'\n';
+                        data_end += 1;
+                    }
+                    buffer[data_end..data_end + PARSER_LOOKAHEAD].fill(0);
+
+                    responses.clear();
+                    let pixels_drawn = self
+                        .parse_datagram(&buffer[..data_end + PARSER_LOOKAHEAD], &mut responses)
+                        .await;
+                    if pixels_drawn > 0 {
+                        *statistics_pixels_for_ip.entry(ip).or_insert(0) += pixels_drawn;
+                    }
+                    rate_limit_bucket.consume(match self.policy.rate_limiter.unit() {
+                        RateLimitUnit::Pixels => pixels_drawn,
+                        RateLimitUnit::Bytes => bytes_read as u64,
+                    });
+                    if self.respond {
+                        for response in response_datagrams(&responses, UDP_MAX_RESPONSE_SIZE) {
+                            if let Err(err) = socket.send_to(response, source).await {
+                                debug!("Failed to send UDP response to {source}: {err}");
+                            }
+                        }
+                    }
+                }
+                _ = statistics_interval.tick() => {
+                    rate_limit_buckets.retain(|_, bucket| !bucket.is_full());
+                    for (ip, bytes) in statistics_bytes_for_ip.drain() {
+                        self.statistics_tx
+                            .send(StatisticsEvent::BytesRead { ip, bytes })
+                            .await
+                            .expect("Statistics channel disconnected");
+                    }
+                    for (ip, pixels) in statistics_pixels_for_ip.drain() {
+                        self.statistics_tx
+                            .send(StatisticsEvent::PixelsDrawn { ip, pixels })
+                            .await
+                            .expect("Statistics channel disconnected");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Parses the datagram and returns the number of pixels drawn.
+    async fn parse_datagram(&self, buffer: &[u8], responses: &mut Vec<u8>) -> u64 {
+        let fb = Arc::clone(&self.fb);
+        let max_rect_area = self.policy.max_rect_area;
+        match self.policy.parser_implementation {
+            ParserImplementation::Original if fb.is_padded() => {
+                let mut parser = OriginalParser::new_padded(fb, max_rect_area);
+                parser.parse(buffer, responses).await;
+                parser.state().pixels_drawn
+            }
+            ParserImplementation::Original => {
+                let mut parser = OriginalParser::new(fb, max_rect_area);
+                parser.parse(buffer, responses).await;
+                parser.state().pixels_drawn
+            }
+            ParserImplementation::Reference => {
+                let mut parser = ReferenceParser::new(fb, max_rect_area);
+                parser.parse(buffer, responses).await;
+                parser.state().pixels_drawn
+            }
+        }
+    }
+}
+
+/// Splits the responses into datagrams of at most `max_size` bytes, so that every datagram contains complete lines.
+fn response_datagrams(mut responses: &[u8], max_size: usize) -> impl Iterator<Item = &[u8]> {
+    std::iter::from_fn(move || {
+        if responses.is_empty() {
+            return None;
+        }
+        let end = if responses.len() <= max_size {
+            responses.len()
+        } else {
+            // A single line longer than a datagram can't be kept whole, so it gets cut at the size limit
+            responses[..max_size]
+                .iter()
+                .rposition(|&byte| byte == b'\n')
+                .map_or(max_size, |newline| newline + 1)
+        };
+        let (datagram, rest) = responses.split_at(end);
+        responses = rest;
+        Some(datagram)
+    })
+}
+
+/// Reports a connection that was rejected for the given reason. Closing the socket is up to the caller.
+pub(crate) fn reject_connection(ip: IpAddr, reason: &str, connection_limiter: &ConnectionLimiter) {
+    debug!("Rejecting connection from {ip}, as {reason}");
+    connection_limiter.count_rejected_connection();
+}
+
+/// Runs the connection until it finishes, or closes it as soon as a reload of the access list denies the IP.
+pub(crate) async fn close_when_denied(
+    connection: impl Future<Output = ()>,
+    ip: IpAddr,
+    access_list: &AccessList,
+    statistics_tx: &Sender<StatisticsEvent>,
+) {
+    tokio::select! {
+        // The connection needs to be polled first, so that it has reported its creation before we report it as closed
+        biased;
+        _ = connection => (),
+        _ = access_list.denied(ip) => {
+            info!("Closing connection from {ip}, as it got denied by the access list");
+            // The connection got dropped in the middle, so it could not report this itself
+            statistics_tx
+                .send(StatisticsEvent::ConnectionClosed { ip })
+                .await
+                .expect("Statistics channel disconnected");
+        }
+    }
+}
+
+/// Creates the parser of the given implementation and handles the connection with it.
+pub async fn handle_connection_with_parser(
+    stream: impl AsyncReadExt + AsyncWriteExt + Send + Unpin,
     ip: IpAddr,
     fb: Arc<FrameBuffer>,
     statistics_tx: Sender<StatisticsEvent>,
+    rate_limiter: &Arc<RateLimiter>,
+    max_rect_area: usize,
+    parser_implementation: ParserImplementation,
+) {
+    // We dispatch to the concrete parser type here, so that the hot parsing loop does not need any dynamic dispatch
+    match parser_implementation {
+        ParserImplementation::Original if fb.is_padded() => {
+            let mut parser = OriginalParser::new_padded(fb, max_rect_area);
+            handle_connection(stream, ip, statistics_tx, rate_limiter, &mut parser).await
+        }
+        ParserImplementation::Original => {
+            let mut parser = OriginalParser::new(fb, max_rect_area);
+            handle_connection(stream, ip, statistics_tx, rate_limiter, &mut parser).await
+        }
+        ParserImplementation::Reference => {
+            let mut parser = ReferenceParser::new(fb, max_rect_area);
+            handle_connection(stream, ip, statistics_tx, rate_limiter, &mut parser).await
+        }
+    }
+}
+
+pub async fn handle_connection(
+    mut stream: impl AsyncReadExt + AsyncWriteExt + Send + Unpin,
+    ip: IpAddr,
+    statistics_tx: Sender<StatisticsEvent>,
+    rate_limiter: &Arc<RateLimiter>,
+    // The parser keeps some things - such as connection offset - for the whole connection lifetime
+    parser: &mut impl Parser,
 ) {
     debug!("Handling connection from {ip}");
 
@@ -76,20 +503,40 @@ pub async fn handle_connection(
     let mut buffer = [0u8; NETWORK_BUFFER_SIZE];
     // Number bytes left over **on the first bytes of the buffer** from the previous loop iteration
     let mut leftover_bytes_in_buffer = 0;
-
-    // We have to keep the some things - such as connection offset - for the whole connection lifetime, so let's define them here
-    let mut parser_state = ParserState::default();
+    // Number of bytes the parser has consumed, i.e. the index of the first byte it has not processed
+    let mut bytes_parsed = 0;
 
     // If we send e.g. an StatisticsEvent::BytesRead for every time we read something from the socket the statistics thread would go crazy.
     // Instead we bulk the statistics and send them pre-aggregated.
     let mut last_statistics = Instant::now();
     let mut statistics_bytes_read: u64 = 0;
+    // The parser counts the pixels over the whole connection, so we remember how many of them we already reported
+    let mut statistics_pixels_reported: u64 = 0;
+    let mut statistics_throttled = Duration::ZERO;
+
+    // Shared with all other connections of the client
+    let rate_limit_bucket = rate_limiter.bucket(ip);
 
     loop {
+        // Clients are only throttled after the data got parsed, so we don't read more than they are allowed to use.
+        // Otherwise a single buffer full of RECT commands could draw orders of magnitude more than the limit.
+        let read_end = match rate_limit_bucket.available() {
+            Some(available) => min(
+                leftover_bytes_in_buffer
+                    + max_rate_limited_read_size(
+                        rate_limiter.unit(),
+                        available,
+                        parser.max_pixels_per_command(),
+                    ),
+                NETWORK_BUFFER_SIZE - PARSER_LOOKAHEAD,
+            ),
+            None => NETWORK_BUFFER_SIZE - PARSER_LOOKAHEAD,
+        };
+
         // Fill the buffer up with new data from the socket
         // If there are any bytes left over from the previous loop iteration leave them as is and but the new data behind
         let bytes_read = match stream
-            .read(&mut buffer[leftover_bytes_in_buffer..NETWORK_BUFFER_SIZE - PARSER_LOOKAHEAD])
+            .read(&mut buffer[leftover_bytes_in_buffer..read_end])
             .await
         {
             Ok(bytes_read) => bytes_read,
@@ -99,7 +546,8 @@ pub async fn handle_connection(
         };
 
         statistics_bytes_read += bytes_read as u64;
-        if last_statistics.elapsed() > STATISTICS_REPORT_INTERVAL {
+        // Also report when the client closed the connection, so that nothing since the last report gets lost
+        if bytes_read == 0 || last_statistics.elapsed() > STATISTICS_REPORT_INTERVAL {
             statistics_tx
                 // We use a blocking call here as we want to process the stats.
                 // Otherwise the stats will lag behind resulting in weird spikes in bytes/s statistics.
@@ -110,8 +558,29 @@ pub async fn handle_connection(
                 })
                 .await
                 .expect("Statistics channel disconnected");
+            let pixels_drawn = parser.state().pixels_drawn;
+            if pixels_drawn > statistics_pixels_reported {
+                statistics_tx
+                    .send(StatisticsEvent::PixelsDrawn {
+                        ip,
+                        pixels: pixels_drawn - statistics_pixels_reported,
+                    })
+                    .await
+                    .expect("Statistics channel disconnected");
+                statistics_pixels_reported = pixels_drawn;
+            }
+            if !statistics_throttled.is_zero() {
+                statistics_tx
+                    .send(StatisticsEvent::Throttled {
+                        ip,
+                        duration: statistics_throttled,
+                    })
+                    .await
+                    .expect("Statistics channel disconnected");
+            }
             last_statistics = Instant::now();
             statistics_bytes_read = 0;
+            statistics_throttled = Duration::ZERO;
         }
 
         let data_end = leftover_bytes_in_buffer + bytes_read;
@@ -132,17 +601,26 @@ pub async fn handle_connection(
                 *i = 0;
             }
 
-            parser_state = parse_pixelflut_commands(
-                &buffer[..data_end + PARSER_LOOKAHEAD],
-                &fb,
-                &mut stream,
-                parser_state,
-            )
-            .await;
+            let pixels_drawn_before = parser.state().pixels_drawn;
+            bytes_parsed = parser
+                .parse(&buffer[..data_end + PARSER_LOOKAHEAD], &mut stream)
+                .await;
+
+            let used = match rate_limiter.unit() {
+                RateLimitUnit::Pixels => parser.state().pixels_drawn - pixels_drawn_before,
+                RateLimitUnit::Bytes => bytes_read as u64,
+            };
+            let throttle = rate_limit_bucket.consume(used);
+            if !throttle.is_zero() {
+                // We don't drop any data, instead we don't read from the socket for a while, so that TCP slows down the client
+                // Timers overshoot, which adds up for the many short sleeps of clients reading only little at a time
+                let throttled_since = Instant::now();
+                tokio::time::sleep(throttle).await;
+                statistics_throttled += throttled_since.elapsed();
+            }
 
-            // IMPORTANT: We have to subtract 1 here, as e.g. we have "PX 0 0\n" data_end is 7 and parser_state.last_byte_parsed is 6.
-            // This happens, because last_byte_parsed is an index starting at 0, so index 6 is from an array of length 7
-            leftover_bytes_in_buffer = data_end - parser_state.last_byte_parsed() - 1;
+            // E.g. for "PX 0 0\nPX" data_end is 9 and bytes_parsed is 7, so "PX" is kept for the next loop iteration
+            leftover_bytes_in_buffer = data_end - bytes_parsed;
 
             // There is no need to leave anything longer than a command can take
             // This prevents malicious clients from sending gibberish and the buffer not getting drained
@@ -151,11 +629,7 @@ pub async fn handle_connection(
 
         if leftover_bytes_in_buffer > 0 {
             // We need to move the leftover bytes to the beginning of the buffer so that the next loop iteration con work on them
-            buffer.copy_within(
-                parser_state.last_byte_parsed() + 1
-                    ..parser_state.last_byte_parsed() + 1 + leftover_bytes_in_buffer,
-                0,
-            );
+            buffer.copy_within(bytes_parsed..bytes_parsed + leftover_bytes_in_buffer, 0);
         }
     }
 
@@ -165,9 +639,29 @@ pub async fn handle_connection(
         .expect("Statistics channel disconnected");
 }
 
+/// Returns how many bytes a rate limited connection can read without drawing much more than the `available` tokens.
+fn max_rate_limited_read_size(
+    unit: RateLimitUnit,
+    available: u64,
+    max_pixels_per_command: usize,
+) -> usize {
+    let max_read_size = match unit {
+        RateLimitUnit::Bytes => available,
+        // No command drawing a pixel is shorter than a PB command, but a single RECT can draw many pixels
+        RateLimitUnit::Pixels => {
+            available.saturating_mul(BINARY_PIXEL_COMMAND_LENGTH as u64)
+                / max_pixels_per_command as u64
+        }
+    };
+    max(
+        usize::try_from(max_read_size).unwrap_or(usize::MAX),
+        MIN_RATE_LIMITED_READ_SIZE,
+    )
+}
+
 /// TODO: Switch to official ip.to_canonical() method when it is stable. **If** it gets stable sometime ;)
 /// See <https://doc.rust-lang.org/std/net/enum.IpAddr.html#method.to_canonical>
-fn ip_to_canonical(ip: IpAddr) -> IpAddr {
+pub(crate) fn ip_to_canonical(ip: IpAddr) -> IpAddr {
     match ip {
         IpAddr::V4(_) => ip,
         IpAddr::V6(v6) => match v6.octets() {
@@ -182,7 +676,7 @@ fn ip_to_canonical(ip: IpAddr) -> IpAddr {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::test::helpers::MockTcpStream;
+    use crate::{access_list::AccessRules, test::helpers::MockTcpStream};
     use rstest::{fixture, rstest};
     use std::time::Duration;
     use tokio::sync::mpsc::{self, Receiver};
@@ -197,11 +691,37 @@ mod test {
         Arc::new(FrameBuffer::new(1920, 1080))
     }
 
+    #[fixture]
+    fn max_rect_area() -> usize {
+        100 * 100
+    }
+
     #[fixture]
     fn statistics_channel() -> (Sender<StatisticsEvent>, Receiver<StatisticsEvent>) {
         mpsc::channel(10000)
     }
 
+    /// Runs [`handle_connection`] with the given parser implementation, so that all tests cover all parsers
+    async fn handle_connection_using(
+        parser_implementation: ParserImplementation,
+        stream: &mut MockTcpStream,
+        ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_tx: Sender<StatisticsEvent>,
+        max_rect_area: usize,
+    ) {
+        handle_connection_with_parser(
+            stream,
+            ip,
+            fb,
+            statistics_tx,
+            &Arc::new(RateLimiter::unlimited()),
+            max_rect_area,
+            parser_implementation,
+        )
+        .await
+    }
+
     #[rstest]
     #[timeout(Duration::from_secs(1))]
     #[case("", "")]
@@ -222,9 +742,20 @@ mod test {
         ip: IpAddr,
         fb: Arc<FrameBuffer>,
         statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        max_rect_area: usize,
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
     ) {
         let mut stream = MockTcpStream::from_input(input);
-        handle_connection(&mut stream, ip, fb, statistics_channel.0).await;
+        handle_connection_using(
+            parser_implementation,
+            &mut stream,
+            ip,
+            fb,
+            statistics_channel.0,
+            max_rect_area,
+        )
+        .await;
 
         assert_eq!(expected, stream.get_output());
     }
@@ -236,18 +767,27 @@ mod test {
     #[case("PX 0 42 abcdef\nPX 0 42\n", "PX 0 42 abcdef\n")]
     #[case("PX 42 0 abcdef\nPX 42 0\n", "PX 42 0 abcdef\n")]
     // With alpha
-    // TODO: At the moment alpha channel is not supported and silently ignored (pixels are painted with 0% transparency)
-    #[case("PX 0 0 ffffffaa\nPX 0 0\n", "PX 0 0 ffffff\n")]
-    #[case("PX 0 0 abcdefaa\nPX 0 0\n", "PX 0 0 abcdef\n")]
-    #[case("PX 0 1 abcdefaa\nPX 0 1\n", "PX 0 1 abcdef\n")]
-    #[case("PX 1 0 abcdefaa\nPX 1 0\n", "PX 1 0 abcdef\n")]
+    #[case("PX 0 0 ffffffff\nPX 0 0\n", "PX 0 0 ffffff\n")]
+    #[case("PX 0 0 abcdefff\nPX 0 0\n", "PX 0 0 abcdef\n")]
+    #[case("PX 0 0 abcdef00\nPX 0 0\n", "PX 0 0 000000\n")]
+    #[case("PX 0 0 ffffffaa\nPX 0 0\n", "PX 0 0 aaaaaa\n")]
+    #[case("PX 0 0 abcdefaa\nPX 0 0\n", "PX 0 0 72899f\n")]
+    #[case("PX 0 1 abcdefaa\nPX 0 1\n", "PX 0 1 72899f\n")]
+    #[case("PX 1 0 abcdefaa\nPX 1 0\n", "PX 1 0 72899f\n")]
+    // Alpha is blended over the current pixel value
+    #[case("PX 0 0 abcdef\nPX 0 0 12345600\nPX 0 0\n", "PX 0 0 abcdef\n")]
+    #[case("PX 0 0 ffffff\nPX 0 0 00000080\nPX 0 0\n", "PX 0 0 7f7f7f\n")]
+    #[case("PX 0 0 ff0000\nPX 0 0 0000ff80\nPX 0 0\n", "PX 0 0 7f0080\n")]
     // Tests invalid bounds
     #[case("PX 9999 0 abcdef\nPX 9999 0\n", "")] // Parsable but outside screen size
     #[case("PX 0 9999 abcdef\nPX 9999 0\n", "")]
     #[case("PX 9999 9999 abcdef\nPX 9999 9999\n", "")]
-    #[case("PX 99999 0 abcdef\nPX 0 99999\n", "")] // Not even parsable because to many digits
+    #[case("PX 99999 0 abcdef\nPX 0 99999\n", "")]
     #[case("PX 0 99999 abcdef\nPX 0 99999\n", "")]
     #[case("PX 99999 99999 abcdef\nPX 99999 99999\n", "")]
+    #[case("PX 999999 0 abcdef\nPX 0 999999\n", "")] // Not even parsable because to many digits
+    #[case("PX 0 999999 abcdef\nPX 0 999999\n", "")]
+    #[case("PX 999999 999999 abcdef\nPX 999999 999999\n", "")]
     // Test invalid inputs
     #[case("PX 0 abcdef\nPX 0 0\n", "PX 0 0 000000\n")]
     #[case("PX 0 1 2 abcdef\nPX 0 0\n", "PX 0 0 000000\n")]
@@ -259,6 +799,10 @@ mod test {
         "PX 0 0 ffffff\nPX 42 42 000000\n"
     )] // The get pixel result is also offseted
     #[case("OFFSET 0 0\nPX 0 42 abcdef\nPX 0 42\n", "PX 0 42 abcdef\n")]
+    #[case(
+        "OFFSET 10 10\nPX 0 0 abcdef\nOFFSET 0 0\nPX 10 10\nPX 0 0\n",
+        "PX 10 10 abcdef\nPX 0 0 000000\n"
+    )]
     #[tokio::test]
     async fn test_setting_pixel(
         #[case] input: &str,
@@ -266,13 +810,230 @@ mod test {
         ip: IpAddr,
         fb: Arc<FrameBuffer>,
         statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        max_rect_area: usize,
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
     ) {
         let mut stream = MockTcpStream::from_input(input);
-        handle_connection(&mut stream, ip, fb, statistics_channel.0).await;
+        handle_connection_using(
+            parser_implementation,
+            &mut stream,
+            ip,
+            fb,
+            statistics_channel.0,
+            max_rect_area,
+        )
+        .await;
+
+        assert_eq!(expected, stream.get_output());
+    }
+
+    #[rstest]
+    #[case("PX 12345 1 abcdef\nPX 12345 1\n", "PX 12345 1 abcdef\n")]
+    #[case("PX 19999 2 abcdef\nPX 19999 2\n", "PX 19999 2 abcdef\n")]
+    #[case("PX 19999 2 abcdefff\nPX 19999 2\n", "PX 19999 2 abcdef\n")]
+    #[case("PX 20000 2 abcdef\nPX 20000 2\n", "")]
+    #[case(
+        "OFFSET 10000 1\nPX 2345 1 abcdef\nOFFSET 0 0\nPX 12345 2\n",
+        "PX 12345 2 abcdef\n"
+    )]
+    #[case("OFFSET 12345 0\nPX 0 0 abcdef\nPX 0 0\n", "PX 0 0 abcdef\n")]
+    #[tokio::test]
+    async fn test_setting_pixel_on_wide_canvas(
+        #[case] input: &str,
+        #[case] expected: &str,
+        ip: IpAddr,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        max_rect_area: usize,
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let fb = Arc::new(FrameBuffer::new(20_000, 3));
+        let mut stream = MockTcpStream::from_input(input);
+        handle_connection_using(
+            parser_implementation,
+            &mut stream,
+            ip,
+            fb,
+            statistics_channel.0,
+            max_rect_area,
+        )
+        .await;
 
         assert_eq!(expected, stream.get_output());
     }
 
+    #[rstest]
+    #[case(b"PB\x00\x00\x00\x00\xab\xcd\xef\xffPX 0 0\n", "PX 0 0 abcdef\n")]
+    #[case(b"PB\x01\x00\x02\x00\xab\xcd\xef\xffPX 1 2\n", "PX 1 2 abcdef\n")]
+    #[case(
+        b"PB\x2c\x01\x90\x01\x12\x34\x56\xffPX 300 400\n",
+        "PX 300 400 123456\n"
+    )]
+    #[case(
+        b"PB\x00\x00\x00\x00\xff\xff\xff\xffPB\x00\x00\x00\x00\x00\x00\x00\x80PX 0 0\n",
+        "PX 0 0 7f7f7f\n"
+    )]
+    #[case(b"PB\x00\x00\x00\x00\xab\xcd\xef\x00PX 0 0\n", "PX 0 0 000000\n")]
+    // Binary commands can contain newlines
+    #[case(b"PB\x0a\x00\x0a\x00\x0a\x0a\x0a\xffPX 10 10\n", "PX 10 10 0a0a0a\n")]
+    // Mixed with text commands
+    #[case(
+        b"PX 0 0 ffffff\nPB\x01\x00\x00\x00\xab\xcd\xef\xffPX 0 0\nPX 1 0\n",
+        "PX 0 0 ffffff\nPX 1 0 abcdef\n"
+    )]
+    #[case(
+        b"OFFSET 10 10\nPB\x00\x00\x00\x00\xab\xcd\xef\xffOFFSET 0 0\nPX 10 10\n",
+        "PX 10 10 abcdef\n"
+    )]
+    // Outside of the screen
+    #[case(b"PB\xff\xff\xff\xff\xab\xcd\xef\xffPX 0 0\n", "PX 0 0 000000\n")]
+    // Incomplete command
+    #[case(b"PB\x00\x00\x00\x00\xab\xcd", "")]
+    #[tokio::test]
+    async fn test_setting_pixel_binary(
+        #[case] input: &[u8],
+        #[case] expected: &str,
+        ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        max_rect_area: usize,
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let mut stream = MockTcpStream::from_bytes(input);
+        handle_connection_using(
+            parser_implementation,
+            &mut stream,
+            ip,
+            fb,
+            statistics_channel.0,
+            max_rect_area,
+        )
+        .await;
+
+        assert_eq!(expected, stream.get_output());
+    }
+
+    #[rstest]
+    // The first read only contains a part of the command
+    #[case(&[4, 16])]
+    #[case(&[1, 19])]
+    // The command is complete, but the following one is not
+    #[case(&[12, 8])]
+    #[case(&[10, 10])]
+    #[tokio::test]
+    async fn test_setting_pixel_binary_split_across_reads(
+        #[case] chunk_sizes: &[usize],
+        ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        max_rect_area: usize,
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let mut stream =
+            MockTcpStream::from_chunks(b"PB\x01\x00\x02\x00\x12\x34\x56\xffPX 1 2\n", chunk_sizes);
+        handle_connection_using(
+            parser_implementation,
+            &mut stream,
+            ip,
+            fb,
+            statistics_channel.0,
+            max_rect_area,
+        )
+        .await;
+
+        assert_eq!("PX 1 2 123456\n", stream.get_output());
+    }
+
+    #[rstest]
+    #[case(
+        "RECT 0 0 2 2 abcdef\nPX 0 0\nPX 1 1\nPX 2 2\n",
+        "PX 0 0 abcdef\nPX 1 1 abcdef\nPX 2 2 000000\n"
+    )]
+    #[case(
+        "RECT 10 20 1 1 abcdef\nPX 10 20\nPX 11 20\nPX 10 21\n",
+        "PX 10 20 abcdef\nPX 11 20 000000\nPX 10 21 000000\n"
+    )]
+    #[case(
+        "RECT 0 0 100 100 abcdef\nPX 99 99\nPX 100 100\n",
+        "PX 99 99 abcdef\nPX 100 100 000000\n"
+    )]
+    // With alpha
+    #[case("RECT 0 0 2 2 abcdefff\nPX 1 1\n", "PX 1 1 abcdef\n")]
+    #[case("RECT 0 0 2 2 abcdef00\nPX 1 1\n", "PX 1 1 000000\n")]
+    #[case(
+        "RECT 0 0 2 2 ffffff\nRECT 1 1 2 2 00000080\nPX 0 0\nPX 1 1\nPX 2 2\n",
+        "PX 0 0 ffffff\nPX 1 1 7f7f7f\nPX 2 2 000000\n"
+    )]
+    // Clipped at the screen borders
+    #[case(
+        "RECT 1910 1070 100 100 abcdef\nPX 1919 1079\n",
+        "PX 1919 1079 abcdef\n"
+    )]
+    #[case("RECT 5000 5000 10 10 abcdef\nPX 0 0\n", "PX 0 0 000000\n")]
+    // Exceeding the maximum area
+    #[case("RECT 0 0 101 100 abcdef\nPX 0 0\n", "PX 0 0 000000\n")]
+    #[case("RECT 0 0 99999 99999 abcdef\nPX 0 0\n", "PX 0 0 000000\n")]
+    // Test offset
+    #[case(
+        "OFFSET 10 10\nRECT 0 0 1 1 abcdef\nOFFSET 0 0\nPX 10 10\nPX 0 0\n",
+        "PX 10 10 abcdef\nPX 0 0 000000\n"
+    )]
+    // Test invalid inputs
+    #[case("RECT 0 0 2 abcdef\nPX 0 0\n", "PX 0 0 000000\n")]
+    #[case("RECT 0 0 123456 1 abcdef\nPX 0 0\n", "PX 0 0 000000\n")]
+    #[case("RECT 0 0 2 2 abcde\nPX 0 0\n", "PX 0 0 000000\n")]
+    #[case("RECT 0 0 2 2 abcdef\nRECT 0 0 2 2\nPX 0 0\n", "PX 0 0 abcdef\n")]
+    #[tokio::test]
+    async fn test_rect_command(
+        #[case] input: &str,
+        #[case] expected: &str,
+        ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        max_rect_area: usize,
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let mut stream = MockTcpStream::from_input(input);
+        handle_connection_using(
+            parser_implementation,
+            &mut stream,
+            ip,
+            fb,
+            statistics_channel.0,
+            max_rect_area,
+        )
+        .await;
+
+        assert_eq!(expected, stream.get_output());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn test_rect_command_disabled(
+        ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let mut stream = MockTcpStream::from_input("RECT 0 0 1 1 abcdef\nPX 0 0\n");
+        handle_connection_using(
+            parser_implementation,
+            &mut stream,
+            ip,
+            fb,
+            statistics_channel.0,
+            0,
+        )
+        .await;
+
+        assert_eq!("PX 0 0 000000\n", stream.get_output());
+    }
+
     #[rstest]
     #[case(5, 5, 0, 0)]
     #[case(6, 6, 0, 0)]
@@ -295,10 +1056,12 @@ mod test {
         #[case] height: usize,
         #[case] offset_x: usize,
         #[case] offset_y: usize,
-        ip: IpAddr,
         fb: Arc<FrameBuffer>,
         statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
     ) {
+        let ip = ip();
         let mut color: u32 = 0;
         let mut fill_commands = String::new();
         let mut read_commands = String::new();
@@ -330,46 +1093,664 @@ mod test {
 
         // Color the pixels
         let mut stream = MockTcpStream::from_input(&fill_commands);
-        handle_connection(
+        handle_connection_using(
+            parser_implementation,
             &mut stream,
             ip,
             Arc::clone(&fb),
             statistics_channel.0.clone(),
+            max_rect_area(),
         )
         .await;
         assert_eq!("", stream.get_output());
 
         // Read the pixels again
         let mut stream = MockTcpStream::from_input(&read_commands);
-        handle_connection(
+        handle_connection_using(
+            parser_implementation,
             &mut stream,
             ip,
             Arc::clone(&fb),
             statistics_channel.0.clone(),
+            max_rect_area(),
         )
         .await;
         assert_eq!(fill_commands, stream.get_output());
 
         // We can also do coloring and reading in a single connection
         let mut stream = MockTcpStream::from_input(&combined_commands);
-        handle_connection(
+        handle_connection_using(
+            parser_implementation,
             &mut stream,
             ip,
             Arc::clone(&fb),
             statistics_channel.0.clone(),
+            max_rect_area(),
         )
         .await;
         assert_eq!(combined_commands_expected, stream.get_output());
 
         // Check that nothing else was colored
         let mut stream = MockTcpStream::from_input(&read_other_pixels_commands);
-        handle_connection(
+        handle_connection_using(
+            parser_implementation,
             &mut stream,
             ip,
             Arc::clone(&fb),
             statistics_channel.0.clone(),
+            max_rect_area(),
         )
         .await;
         assert_eq!(read_other_pixels_commands_expected, stream.get_output());
     }
+
+    /// Starts an [`UdpNetwork`] on a random port and returns a client socket connected to it
+    async fn start_udp_network(
+        fb: Arc<FrameBuffer>,
+        statistics_tx: Sender<StatisticsEvent>,
+        parser_implementation: ParserImplementation,
+        respond: bool,
+    ) -> UdpSocket {
+        start_udp_network_on(
+            "127.0.0.1:0",
+            fb,
+            statistics_tx,
+            ConnectionPolicy::unlimited(max_rect_area(), parser_implementation),
+            respond,
+        )
+        .await
+    }
+
+    async fn start_udp_network_on(
+        address: &str,
+        fb: Arc<FrameBuffer>,
+        statistics_tx: Sender<StatisticsEvent>,
+        policy: ConnectionPolicy,
+        respond: bool,
+    ) -> UdpSocket {
+        let server = UdpSocket::bind(address).await.unwrap();
+        let client = UdpSocket::bind(address).await.unwrap();
+        client.connect(server.local_addr().unwrap()).await.unwrap();
+
+        let network = UdpNetwork::new("", fb, statistics_tx, policy, respond);
+        tokio::spawn(async move { network.serve(server).await });
+
+        client
+    }
+
+    #[rstest]
+    #[case(RateLimitUnit::Pixels, "RECT 0 0 10 150 ffffff\n", "PX 0 0\n")]
+    #[case(RateLimitUnit::Bytes, &"PX 0 0 ffffff\n".repeat(107), "PX 0 0\n")]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_rate_limit(
+        #[case] unit: RateLimitUnit,
+        #[case] first_chunk: &str,
+        #[case] second_chunk: &str,
+        ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let (statistics_tx, mut statistics_rx) = statistics_channel;
+        // The first chunk uses 1500 pixels or bytes, so the client needs to wait half a second before we read the second one
+        let rate_limiter = Arc::new(RateLimiter::new(unit, 1000, 64));
+        let input = format!("{first_chunk}{second_chunk}");
+        let mut stream =
+            MockTcpStream::from_chunks(input.as_bytes(), &[first_chunk.len(), second_chunk.len()]);
+
+        let start = Instant::now();
+        handle_connection_with_parser(
+            &mut stream,
+            ip,
+            fb,
+            statistics_tx,
+            &rate_limiter,
+            max_rect_area(),
+            parser_implementation,
+        )
+        .await;
+        assert!(start.elapsed() >= Duration::from_millis(450));
+        // Nothing got dropped
+        assert_eq!(stream.get_output(), "PX 0 0 ffffff\n");
+
+        let mut throttled = Duration::ZERO;
+        while let Ok(event) = statistics_rx.try_recv() {
+            if let StatisticsEvent::Throttled {
+                ip: throttled_ip,
+                duration,
+            } = event
+            {
+                assert_eq!(throttled_ip, ip);
+                throttled += duration;
+            }
+        }
+        assert!(throttled >= Duration::from_millis(450), "{throttled:?}");
+    }
+
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_rate_limit_rect_flood(
+        ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        max_rect_area: usize,
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        // Every RECT uses the whole second worth of tokens, so only the first ones may be drawn before throttling
+        let rate_limiter = Arc::new(RateLimiter::new(RateLimitUnit::Pixels, 100, 64));
+        let input: String = (0..1000)
+            .map(|y| format!("RECT 0 {y} 100 1 ffffff\n"))
+            .collect();
+        let mut stream = MockTcpStream::from_input(&input);
+
+        let connection = handle_connection_with_parser(
+            &mut stream,
+            ip,
+            Arc::clone(&fb),
+            statistics_channel.0,
+            &rate_limiter,
+            max_rect_area,
+            parser_implementation,
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), connection)
+                .await
+                .is_err(),
+            "Expected the connection to be throttled"
+        );
+
+        let rows_drawn = (0..1000)
+            .filter(|&y| fb.get(0, y) == Some(0xffffff))
+            .count();
+        assert!((1..=3).contains(&rows_drawn), "{rows_drawn} rows drawn");
+    }
+
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_pixel_statistics(
+        ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        max_rect_area: usize,
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let (statistics_tx, mut statistics_rx) = statistics_channel;
+        let (mut client, server) = tokio::io::duplex(1024);
+        let rate_limiter = Arc::new(RateLimiter::unlimited());
+
+        let connection = handle_connection_with_parser(
+            server,
+            ip,
+            fb,
+            statistics_tx,
+            &rate_limiter,
+            max_rect_area,
+            parser_implementation,
+        );
+        let client = async move {
+            client
+                .write_all(
+                    b"RECT 0 0 10 10 ffffff\nPX 0 0 ffffff\nPB\x00\x00\x00\x00\xff\xff\xff\xff",
+                )
+                .await
+                .unwrap();
+            // The statistics are sent with the first read after the report interval
+            tokio::time::sleep(STATISTICS_REPORT_INTERVAL * 2).await;
+            client.write_all(b"PX 1 1 ffffff\n").await.unwrap();
+        };
+        tokio::join!(connection, client);
+
+        let mut pixels = 0;
+        while let Ok(event) = statistics_rx.try_recv() {
+            if let StatisticsEvent::PixelsDrawn {
+                ip: drawing_ip,
+                pixels: drawn,
+            } = event
+            {
+                assert_eq!(drawing_ip, ip);
+                pixels += drawn;
+            }
+        }
+        // RECT commands count with their full area, the last pixel gets reported when the connection is closed
+        assert_eq!(pixels, 10 * 10 + 1 + 1 + 1);
+    }
+
+    #[rstest]
+    #[case(true, b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 1234\r\n", "192.0.2.1")]
+    // Health checks of the proxy itself
+    #[case(true, b"PROXY UNKNOWN\r\n", "127.0.0.1")]
+    #[case(false, b"", "127.0.0.1")]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_proxy_protocol(
+        #[case] proxy_protocol: bool,
+        #[case] header: &[u8],
+        #[case] expected_ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+    ) {
+        let (statistics_tx, mut statistics_rx) = statistics_channel;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let network = Network::new(
+            &[address.to_string()],
+            fb,
+            statistics_tx,
+            ConnectionPolicy::unlimited(max_rect_area(), ParserImplementation::default()),
+            proxy_protocol,
+        );
+        tokio::spawn(async move { network.serve(listener).await });
+
+        let mut client = tokio::net::TcpStream::connect(address).await.unwrap();
+        client.write_all(header).await.unwrap();
+        client.write_all(b"SIZE\n").await.unwrap();
+        let mut response = [0u8; 15];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"SIZE 1920 1080\n");
+
+        assert!(matches!(
+            statistics_rx.recv().await,
+            Some(StatisticsEvent::ConnectionCreated { ip }) if ip == expected_ip
+        ));
+    }
+
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_connection_limit(
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let connection_limiter = Arc::new(ConnectionLimiter::new(Some(2), None, 64));
+        let network = Network::new(
+            &[address.to_string()],
+            fb,
+            statistics_channel.0,
+            ConnectionPolicy {
+                connection_limiter: Arc::clone(&connection_limiter),
+                ..ConnectionPolicy::unlimited(max_rect_area(), ParserImplementation::default())
+            },
+            false,
+        );
+        tokio::spawn(async move { network.serve(listener).await });
+
+        let mut response = [0u8; 15];
+        let mut connections = Vec::new();
+        for _ in 0..2 {
+            let mut client = tokio::net::TcpStream::connect(address).await.unwrap();
+            client.write_all(b"SIZE\n").await.unwrap();
+            client.read_exact(&mut response).await.unwrap();
+            connections.push(client);
+        }
+
+        // The third connection gets closed right away
+        let mut client = tokio::net::TcpStream::connect(address).await.unwrap();
+        let _ = client.write_all(b"SIZE\n").await;
+        assert!(!matches!(client.read(&mut response).await, Ok(read) if read > 0));
+        assert_eq!(connection_limiter.rejected_connections(), 1);
+
+        // Closing a connection frees its slot
+        drop(connections.pop());
+        while connection_limiter.connections_for_ip(ip()) > 1 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut client = tokio::net::TcpStream::connect(address).await.unwrap();
+        client.write_all(b"SIZE\n").await.unwrap();
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"SIZE 1920 1080\n");
+    }
+
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_proxy_protocol_header_missing(
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let network = Network::new(
+            &[address.to_string()],
+            fb,
+            statistics_channel.0,
+            ConnectionPolicy::unlimited(max_rect_area(), ParserImplementation::default()),
+            true,
+        );
+        tokio::spawn(async move { network.serve(listener).await });
+
+        // Clients connecting directly can't pretend to be someone else
+        let mut client = tokio::net::TcpStream::connect(address).await.unwrap();
+        client.write_all(b"SIZE\nSIZE\nSIZE\n").await.unwrap();
+        let mut response = Vec::new();
+        // Closing the connection with unread data might reset it, so both an error and EOF are fine
+        let _ = client.read_to_end(&mut response).await;
+        assert!(response.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_multiple_unix_sockets(
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+    ) {
+        let (statistics_tx, mut statistics_rx) = statistics_channel;
+        let socket_paths = ["first", "second"].map(|name| {
+            std::env::temp_dir().join(format!(
+                "breakwater_test_{}_{name}.sock",
+                std::process::id()
+            ))
+        });
+        // A socket left over from a previous run must not prevent us from starting
+        drop(std::os::unix::net::UnixListener::bind(&socket_paths[0]).unwrap());
+
+        let listen_addresses = socket_paths
+            .iter()
+            .map(|path| format!("{UNIX_SOCKET_PREFIX}{}", path.display()))
+            .collect::<Vec<_>>();
+        // Unix domain sockets are neither affected by the access list nor by the limits of localhost
+        let network = Network::new(
+            &listen_addresses,
+            fb,
+            statistics_tx,
+            ConnectionPolicy {
+                connection_limiter: Arc::new(ConnectionLimiter::new(Some(1), None, 64)),
+                rate_limiter: Arc::new(RateLimiter::new(RateLimitUnit::Bytes, 1, 64)),
+                access_list: Arc::new(AccessList::new("allow 10.0.0.0/8".parse().unwrap())),
+                ..ConnectionPolicy::unlimited(max_rect_area(), ParserImplementation::default())
+            },
+            false,
+        );
+        let server = tokio::spawn(async move { network.listen().await });
+        let connect = |path| async move {
+            loop {
+                match tokio::net::UnixStream::connect(path).await {
+                    Ok(stream) => return stream,
+                    // The server might not be listening yet
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+
+        // Both sockets draw on the same canvas
+        let mut first = connect(&socket_paths[0]).await;
+        let mut second = connect(&socket_paths[1]).await;
+        first.write_all(b"PX 1 2 abcdef\nSIZE\n").await.unwrap();
+        let mut response = [0u8; 15];
+        first.read_exact(&mut response).await.unwrap();
+        second.write_all(b"PX 1 2\n").await.unwrap();
+        let mut response = [0u8; 14];
+        second.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"PX 1 2 abcdef\n");
+
+        assert!(matches!(
+            statistics_rx.recv().await,
+            Some(StatisticsEvent::ConnectionCreated { ip }) if ip == UNIX_SOCKET_CLIENT_IP
+        ));
+
+        // The socket files are removed on shutdown
+        server.abort();
+        assert!(server.await.unwrap_err().is_cancelled());
+        for path in socket_paths {
+            assert!(!path.exists(), "{} still exists", path.display());
+        }
+    }
+
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_close_when_denied(
+        ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+    ) {
+        let (statistics_tx, mut statistics_rx) = statistics_channel;
+        let access_list = Arc::new(AccessList::allow_all());
+        let (mut client, server) = tokio::io::duplex(1024);
+
+        let connection_access_list = Arc::clone(&access_list);
+        tokio::spawn(async move {
+            let rate_limiter = Arc::new(RateLimiter::unlimited());
+            let connection = handle_connection_with_parser(
+                server,
+                ip,
+                fb,
+                statistics_tx.clone(),
+                &rate_limiter,
+                max_rect_area(),
+                ParserImplementation::default(),
+            );
+            close_when_denied(connection, ip, &connection_access_list, &statistics_tx).await
+        });
+
+        client.write_all(b"SIZE\n").await.unwrap();
+        let mut response = [0u8; 15];
+        client.read_exact(&mut response).await.unwrap();
+        assert_eq!(&response, b"SIZE 1920 1080\n");
+
+        access_list.set_rules("deny 127.0.0.0/8".parse().unwrap());
+        // The server closed the connection
+        assert_eq!(client.read(&mut response).await.unwrap(), 0);
+
+        assert!(matches!(
+            statistics_rx.recv().await,
+            Some(StatisticsEvent::ConnectionCreated { .. })
+        ));
+        assert!(matches!(
+            statistics_rx.recv().await,
+            Some(StatisticsEvent::ConnectionClosed { .. })
+        ));
+    }
+
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_udp_responses(
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let client = start_udp_network(fb, statistics_channel.0, parser_implementation, true).await;
+        let mut response = [0; 1024];
+
+        // The last command does not need a newline
+        client.send(b"PX 1 2 abcdef\nPX 1 2").await.unwrap();
+        let read = client.recv(&mut response).await.unwrap();
+        assert_eq!(&response[..read], b"PX 1 2 abcdef\n");
+
+        // Every datagram starts without an offset
+        client.send(b"OFFSET 10 10\n").await.unwrap();
+        client.send(b"PX 0 0 123456\nPX 0 0\n").await.unwrap();
+        let read = client.recv(&mut response).await.unwrap();
+        assert_eq!(&response[..read], b"PX 0 0 123456\n");
+    }
+
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_udp_without_responses(
+        ip: IpAddr,
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let (statistics_tx, mut statistics_rx) = statistics_channel;
+        let client =
+            start_udp_network(Arc::clone(&fb), statistics_tx, parser_implementation, false).await;
+
+        client.send(b"PX 1 2 abcdef\nPX 1 2\n").await.unwrap();
+        while fb.get(1, 2) != Some(0xefcdab) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let mut response = [0; 1024];
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), client.recv(&mut response))
+                .await
+                .is_err(),
+            "Expected no response"
+        );
+
+        // The statistics are sent after the report interval, even if no further datagrams arrive
+        let (mut bytes, mut pixels) = (0, 0);
+        while bytes < 21 || pixels < 1 {
+            match statistics_rx.recv().await {
+                Some(StatisticsEvent::BytesRead {
+                    ip: read_ip,
+                    bytes: read,
+                }) => {
+                    assert_eq!(read_ip, ip);
+                    bytes += read;
+                }
+                Some(StatisticsEvent::PixelsDrawn {
+                    ip: drawing_ip,
+                    pixels: drawn,
+                }) => {
+                    assert_eq!(drawing_ip, ip);
+                    pixels += drawn;
+                }
+                other => panic!("Unexpected statistics event {other:?}"),
+            }
+        }
+        assert_eq!(bytes, 21);
+        assert_eq!(pixels, 1);
+    }
+
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_udp_rate_limit(
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let client = start_udp_network_on(
+            "127.0.0.1:0",
+            fb,
+            statistics_channel.0,
+            ConnectionPolicy {
+                rate_limiter: Arc::new(RateLimiter::new(RateLimitUnit::Pixels, 100, 64)),
+                ..ConnectionPolicy::unlimited(max_rect_area(), parser_implementation)
+            },
+            true,
+        )
+        .await;
+
+        // Uses 200 pixels, so the client owes 100 pixels and has to wait for a second
+        client.send(b"RECT 0 0 10 20 ffffff\n").await.unwrap();
+        let start = Instant::now();
+        client.send(b"PX 0 0\n").await.unwrap();
+        let mut response = [0; 1024];
+        assert!(
+            tokio::time::timeout(Duration::from_millis(500), client.recv(&mut response))
+                .await
+                .is_err(),
+            "Expected the datagram to be dropped"
+        );
+
+        while start.elapsed() < Duration::from_millis(1100) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        client.send(b"PX 0 0\n").await.unwrap();
+        let read = client.recv(&mut response).await.unwrap();
+        assert_eq!(&response[..read], b"PX 0 0 ffffff\n");
+    }
+
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_udp_access_list(
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+    ) {
+        let access_list = Arc::new(AccessList::new("deny 127.0.0.0/8".parse().unwrap()));
+        let client = start_udp_network_on(
+            "127.0.0.1:0",
+            Arc::clone(&fb),
+            statistics_channel.0,
+            ConnectionPolicy {
+                access_list: Arc::clone(&access_list),
+                ..ConnectionPolicy::unlimited(max_rect_area(), ParserImplementation::default())
+            },
+            true,
+        )
+        .await;
+
+        client.send(b"PX 1 2 abcdef\nPX 1 2\n").await.unwrap();
+        let mut response = [0; 1024];
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), client.recv(&mut response))
+                .await
+                .is_err(),
+            "Expected the datagram to be dropped"
+        );
+        assert_eq!(fb.get(1, 2), Some(0));
+
+        // Datagrams are checked against the current rules
+        access_list.set_rules(AccessRules::default());
+        client.send(b"PX 1 2 abcdef\nPX 1 2\n").await.unwrap();
+        let read = client.recv(&mut response).await.unwrap();
+        assert_eq!(&response[..read], b"PX 1 2 abcdef\n");
+    }
+
+    #[rstest]
+    #[case("", 14, &[])]
+    #[case("PX 1 2 abcdef\n", 14, &["PX 1 2 abcdef\n"])]
+    #[case("PX 1 2 abcdef\nPX 3 4 abcdef\n", 20, &["PX 1 2 abcdef\n", "PX 3 4 abcdef\n"])]
+    #[case(
+        "PX 1 2 abcdef\nPX 3 4 abcdef\nSIZE 1920 1080\n",
+        30,
+        &["PX 1 2 abcdef\nPX 3 4 abcdef\n", "SIZE 1920 1080\n"]
+    )]
+    #[case("PX 1 2 abcdef\n", 10, &["PX 1 2 abc", "def\n"])]
+    fn test_response_datagrams(
+        #[case] responses: &str,
+        #[case] max_size: usize,
+        #[case] expected: &[&str],
+    ) {
+        let datagrams = response_datagrams(responses.as_bytes(), max_size)
+            .map(|datagram| std::str::from_utf8(datagram).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(datagrams, expected);
+    }
+
+    #[rstest]
+    #[timeout(Duration::from_secs(5))]
+    #[tokio::test]
+    async fn test_udp_largest_ipv6_datagram(
+        fb: Arc<FrameBuffer>,
+        statistics_channel: (Sender<StatisticsEvent>, Receiver<StatisticsEvent>),
+        #[values(ParserImplementation::Original, ParserImplementation::Reference)]
+        parser_implementation: ParserImplementation,
+    ) {
+        let client = start_udp_network_on(
+            "[::1]:0",
+            fb,
+            statistics_channel.0,
+            ConnectionPolicy::unlimited(max_rect_area(), parser_implementation),
+            true,
+        )
+        .await;
+
+        // IPv6 allows 20 bytes more than IPv4, the command at the very end must not be cut off
+        let command = b"PX 5 6 abcdef\nPX 5 6\n";
+        let mut datagram = vec![b'\n'; 65_527 - command.len()];
+        datagram.extend_from_slice(command);
+        client.send(&datagram).await.unwrap();
+
+        let mut response = [0; 1024];
+        let read = client.recv(&mut response).await.unwrap();
+        assert_eq!(&response[..read], b"PX 5 6 abcdef\n");
+    }
 }
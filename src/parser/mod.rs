@@ -0,0 +1,247 @@
+use std::future::Future;
+
+use clap::ValueEnum;
+use tokio::io::AsyncWriteExt;
+
+pub mod original;
+pub mod reference;
+
+pub const PARSER_LOOKAHEAD: usize = "RECT 12345 12345 12345 12345 rrggbbaa\n".len(); // Longest possible command
+/// "PB" followed by x (u16), y (u16) and the rgba color (4 x u8)
+pub const BINARY_PIXEL_COMMAND_LENGTH: usize = 2 + 2 + 2 + 4;
+pub const HELP_TEXT: &[u8] = "\
+Pixelflut server powered by breakwater https://github.com/sbernauer/breakwater
+Available commands:
+HELP: Show this help
+PX x y rrggbb: Color the pixel (x,y) with the given hexadecimal color
+PX x y rrggbbaa: Blend the hexadecimal color rrggbb with the alpha value aa (00 = transparent, ff = opaque) over the pixel (x,y)
+PX x y: Get the color value of the pixel (x,y)
+SIZE: Get the size of the drawing surface, e.g. `SIZE 1920 1080`
+PBxyrgba: Binary version of the PX command, x and y are little-endian u16 followed by the bytes r, g, b and a (without newline)
+OFFSET x y: Apply offset (x,y) to all further pixel draws on this connection
+RECT x y w h rrggbb: Fill the rectangle at (x,y) with width w and height h with the given hexadecimal color (the maximum area is limited by the server)
+RECT x y w h rrggbbaa: Blend the hexadecimal color rrggbb with the alpha value aa over the rectangle at (x,y) with width w and height h
+".as_bytes();
+
+/// The parser implementations that can be selected via the command line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ParserImplementation {
+    /// Hand-unrolled parser tuned for maximum performance
+    #[default]
+    Original,
+    /// Straightforward parser that is slower, but easy to verify
+    Reference,
+}
+
+/// Per-connection state that needs to be kept between multiple calls to [`Parser::parse`].
+#[derive(Clone, Default, Debug, PartialEq, Eq)]
+pub struct ParserState {
+    pub connection_x_offset: usize,
+    pub connection_y_offset: usize,
+    /// Number of pixels the client has drawn so far, used for rate limiting.
+    /// Pixels outside of the screen are counted as well, RECT commands count with their full area.
+    pub pixels_drawn: u64,
+}
+
+pub trait Parser {
+    /// Parses all commands in `buffer` and writes the responses to `stream`.
+    /// Returns the number of bytes consumed, i.e. the index of the first byte following the last fully parsed command.
+    /// 0 means that nothing could be parsed, e.g. because the buffer starts with an incomplete command.
+    ///
+    /// The last [`PARSER_LOOKAHEAD`] bytes of `buffer` are not part of the received data but must be zeroed,
+    /// so that parsers can look ahead without checking bounds all the time.
+    fn parse(
+        &mut self,
+        buffer: &[u8],
+        stream: impl AsyncWriteExt + Send + Unpin,
+    ) -> impl Future<Output = usize> + Send;
+
+    /// Returns the state kept for the connection, e.g. the offset set by the client.
+    fn state(&self) -> &ParserState;
+
+    /// Returns the maximum number of pixels a single command can draw, e.g. the area of the largest allowed RECT.
+    fn max_pixels_per_command(&self) -> usize;
+}
+
+pub(crate) struct RectArguments {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub rgb: u32,
+    pub alpha: u8,
+}
+
+/// Parses `x y w h rrggbb[aa]\n` of the RECT command starting at `buffer[i]`.
+/// Returns the parsed arguments together with the index of the terminating newline.
+///
+/// In contrast to the PX command this is not hand-unrolled, as RECT is sent much less frequently.
+/// The [`ReferenceParser`](reference::ReferenceParser) has its own implementation, so that both can be compared.
+#[inline(always)]
+pub(crate) fn parse_rect_arguments(buffer: &[u8], mut i: usize) -> Option<(RectArguments, usize)> {
+    let mut numbers = [0; 4];
+    for number in &mut numbers {
+        *number = parse_number(buffer, &mut i)?;
+        if buffer[i] != b' ' {
+            return None;
+        }
+        i += 1;
+    }
+    let [x, y, width, height] = numbers;
+
+    let rgb = (ASCII_HEXADECIMAL_VALUES[buffer[i + 4] as usize] as u32) << 20
+        | (ASCII_HEXADECIMAL_VALUES[buffer[i + 5] as usize] as u32) << 16
+        | (ASCII_HEXADECIMAL_VALUES[buffer[i + 2] as usize] as u32) << 12
+        | (ASCII_HEXADECIMAL_VALUES[buffer[i + 3] as usize] as u32) << 8
+        | (ASCII_HEXADECIMAL_VALUES[buffer[i] as usize] as u32) << 4
+        | (ASCII_HEXADECIMAL_VALUES[buffer[i + 1] as usize] as u32);
+
+    let (alpha, newline) = if buffer[i + 6] == b'\n' {
+        (0xff, i + 6)
+    } else if buffer[i + 8] == b'\n' {
+        (
+            ASCII_HEXADECIMAL_VALUES[buffer[i + 6] as usize] << 4
+                | ASCII_HEXADECIMAL_VALUES[buffer[i + 7] as usize],
+            i + 8,
+        )
+    } else {
+        return None;
+    };
+
+    Some((
+        RectArguments {
+            x,
+            y,
+            width,
+            height,
+            rgb,
+            alpha,
+        },
+        newline,
+    ))
+}
+
+/// Parses a decimal number of up to 5 digits starting at `buffer[*i]` and advances `i` behind it.
+#[inline(always)]
+pub(crate) fn parse_number(buffer: &[u8], i: &mut usize) -> Option<usize> {
+    let start = *i;
+    let mut number = 0;
+    while *i - start < 5 && buffer[*i].is_ascii_digit() {
+        number = 10 * number + (buffer[*i] - b'0') as usize;
+        *i += 1;
+    }
+
+    (*i > start).then_some(number)
+}
+
+#[inline(always)]
+pub fn from_hex_char_map(char: u8) -> u8 {
+    match char {
+        b'0'..=b'9' => char - b'0',
+        b'a'..=b'f' => char - b'a' + 10,
+        b'A'..=b'F' => char - b'A' + 10,
+        _ => 0,
+    }
+}
+
+// fn main() {
+// let numbers = (0..=255)
+//     .map(|char| match char {
+//         b'0'..=b'9' => char - b'0',
+//         b'a'..=b'f' => char - b'a' + 10,
+//         b'A'..=b'F' => char - b'A' + 10,
+//         _ => 0,
+//     })
+//     .map(|number| number.to_string())
+//     .collect::<Vec<String>>();
+// println!("{}", numbers.join(", "));
+// }
+pub(crate) const ASCII_HEXADECIMAL_VALUES: [u8; 256] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 0, 0, 0, 0, 0, 0,
+    0, 10, 11, 12, 13, 14, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 10, 11, 12, 13, 14, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0,
+];
+
+#[inline(always)]
+pub fn from_hex_char_lookup(char: u8) -> u8 {
+    ASCII_HEXADECIMAL_VALUES[char as usize]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::helpers::assert_parsers_are_equivalent;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_from_hex_char() {
+        for c in 0..=255 {
+            assert_eq!(from_hex_char_map(c), from_hex_char_lookup(c));
+        }
+    }
+
+    /// Generates (mostly) valid commands, so that the parsers have to do more than skipping garbage
+    fn command() -> impl Strategy<Value = Vec<u8>> {
+        let coordinate = prop_oneof![0..80_usize, 0..100_000_usize];
+        let color = prop_oneof!["[0-9a-f]{6}", "[0-9a-fA-F]{8}", "[0-9a-zA-Z ]{0,9}",];
+        prop_oneof![
+            (coordinate.clone(), coordinate.clone(), color.clone())
+                .prop_map(|(x, y, color)| format!("PX {x} {y} {color}\n").into_bytes()),
+            (coordinate.clone(), coordinate.clone())
+                .prop_map(|(x, y)| format!("PX {x} {y}\n").into_bytes()),
+            (0..100_u16, 0..100_u16, any::<[u8; 4]>()).prop_map(|(x, y, rgba)| {
+                [b"PB".as_slice(), &x.to_le_bytes(), &y.to_le_bytes(), &rgba].concat()
+            }),
+            (coordinate.clone(), coordinate.clone())
+                .prop_map(|(x, y)| format!("OFFSET {x} {y}\n").into_bytes()),
+            (
+                coordinate.clone(),
+                coordinate.clone(),
+                0..30_usize,
+                0..30_usize,
+                color
+            )
+                .prop_map(
+                    |(x, y, w, h, color)| format!("RECT {x} {y} {w} {h} {color}\n").into_bytes()
+                ),
+            Just(b"SIZE\n".to_vec()),
+            Just(b"HELP\n".to_vec()),
+            "[ -~\n]{0,30}".prop_map(String::into_bytes),
+            prop::collection::vec(any::<u8>(), 0..30),
+        ]
+    }
+
+    fn commands() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(command(), 0..50).prop_map(|commands| commands.concat())
+    }
+
+    proptest! {
+        #[test]
+        fn test_parsers_are_equivalent(
+            input in commands(),
+            chunk_sizes in prop::collection::vec(1..64_usize, 0..20),
+        ) {
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(assert_parsers_are_equivalent(&input, &chunk_sizes));
+        }
+
+        #[test]
+        fn test_parsers_are_equivalent_on_arbitrary_bytes(
+            input in prop::collection::vec(any::<u8>(), 0..1000),
+            chunk_sizes in prop::collection::vec(1..64_usize, 0..20),
+        ) {
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(assert_parsers_are_equivalent(&input, &chunk_sizes));
+        }
+    }
+}
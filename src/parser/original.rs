@@ -0,0 +1,437 @@
+use std::sync::Arc;
+
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    framebuffer::FrameBuffer,
+    parser::{
+        parse_rect_arguments, Parser, ParserState, ASCII_HEXADECIMAL_VALUES,
+        BINARY_PIXEL_COMMAND_LENGTH, HELP_TEXT, PARSER_LOOKAHEAD,
+    },
+};
+
+/// Hand-unrolled parser tuned for maximum performance.
+///
+/// The parser can read up to 5 digits of x or y coordinates, which is enough for 16K (15360 × 8640) and
+/// for even wider canvases made of multiple outputs stitched together.
+///
+/// `PADDED` parsers draw on padded framebuffers without bound checks, see [`FrameBuffer::new_padded`].
+/// Choosing this once per parser keeps the check for the framebuffer layout out of the hot loop.
+pub struct OriginalParser<const PADDED: bool = false> {
+    fb: Arc<FrameBuffer>,
+    state: ParserState,
+    /// Maximum number of pixels a single RECT command is allowed to fill, 0 disables the RECT command
+    max_rect_area: usize,
+}
+
+impl OriginalParser {
+    pub fn new(fb: Arc<FrameBuffer>, max_rect_area: usize) -> Self {
+        OriginalParser {
+            fb,
+            state: ParserState::default(),
+            max_rect_area,
+        }
+    }
+}
+
+impl OriginalParser<true> {
+    /// Panics if the framebuffer was not created by [`FrameBuffer::new_padded`].
+    pub fn new_padded(fb: Arc<FrameBuffer>, max_rect_area: usize) -> Self {
+        assert!(fb.is_padded(), "Framebuffer is not padded");
+        OriginalParser {
+            fb,
+            state: ParserState::default(),
+            max_rect_area,
+        }
+    }
+}
+
+impl<const PADDED: bool> OriginalParser<PADDED> {
+    #[inline(always)]
+    fn set_pixel(fb: &FrameBuffer, x: usize, y: usize, rgba: u32) {
+        if PADDED {
+            // SAFETY: Padded parsers can only be created for padded framebuffers
+            unsafe { fb.set_padded(x, y, rgba) }
+        } else {
+            fb.set(x, y, rgba)
+        }
+    }
+}
+
+impl<const PADDED: bool> Parser for OriginalParser<PADDED> {
+    async fn parse(
+        &mut self,
+        buffer: &[u8],
+        mut stream: impl AsyncWriteExt + Send + Unpin,
+    ) -> usize {
+        let fb = &self.fb;
+        let mut bytes_parsed = 0;
+        let mut connection_x_offset = self.state.connection_x_offset;
+        let mut connection_y_offset = self.state.connection_y_offset;
+        let mut pixels_drawn = self.state.pixels_drawn;
+        let max_rect_area = self.max_rect_area;
+
+        let mut x: usize;
+        let mut y: usize;
+
+        let mut i = 0; // We can't use a for loop here because Rust don't lets use skip characters by incrementing i
+        let loop_end = buffer.len().saturating_sub(PARSER_LOOKAHEAD); // Let's extract the .len() call and the subtraction into it's own variable so we only compute it once
+
+        while i < loop_end {
+            // Check for buffer[i] = "PX "
+            if unsafe { (buffer.as_ptr().add(i) as *const u32).read_unaligned() } & 0x00ff_ffff
+                == 0x50582000_u32.swap_bytes()
+            {
+                i += 3;
+                // Parse first x coordinate char
+                if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                    x = (buffer[i] - b'0') as usize;
+                    i += 1;
+
+                    // Parse optional second x coordinate char
+                    if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                        // TODO: Test bitshifts and add instead of multiplication
+                        // i = (i << 3) + (i << 1);
+                        // i = (i * 8) + (i * 2);
+                        // i = 8i + 2i
+                        // i = 10i
+                        x = 10 * x + (buffer[i] - b'0') as usize;
+                        i += 1;
+
+                        // Parse optional third x coordinate char
+                        if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                            x = 10 * x + (buffer[i] - b'0') as usize;
+                            i += 1;
+
+                            // Parse optional forth x coordinate char
+                            if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                x = 10 * x + (buffer[i] - b'0') as usize;
+                                i += 1;
+
+                                // Parse optional fifth x coordinate char
+                                if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                    x = 10 * x + (buffer[i] - b'0') as usize;
+                                    i += 1;
+                                }
+                            }
+                        }
+                    }
+
+                    // Separator between x and y
+                    if buffer[i] == b' ' {
+                        i += 1;
+
+                        // Parse first y coordinate char
+                        if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                            y = (buffer[i] - b'0') as usize;
+                            i += 1;
+
+                            // Parse optional second y coordinate char
+                            if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                y = 10 * y + (buffer[i] - b'0') as usize;
+                                i += 1;
+
+                                // Parse optional third y coordinate char
+                                if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                    y = 10 * y + (buffer[i] - b'0') as usize;
+                                    i += 1;
+
+                                    // Parse optional forth y coordinate char
+                                    if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                        y = 10 * y + (buffer[i] - b'0') as usize;
+                                        i += 1;
+
+                                        // Parse optional fifth y coordinate char
+                                        if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                            y = 10 * y + (buffer[i] - b'0') as usize;
+                                            i += 1;
+                                        }
+                                    }
+                                }
+                            }
+
+                            x += connection_x_offset;
+                            y += connection_y_offset;
+
+                            // Separator between coordinates and color
+                            if buffer[i] == b' ' {
+                                i += 1;
+
+                                // TODO: Determine what clients use more: RGB or RGBA.
+                                // If RGBA is used more often move the RGB code below the RGBA code
+
+                                // Must be followed by 6 bytes RGB and newline or ...
+                                if buffer[i + 6] == b'\n' {
+                                    bytes_parsed = i + 7;
+                                    i += 7; // We can advance one byte more than normal as we use continue and therefore not get incremented at the end of the loop
+
+                                    // 30% slower (38,334 ms vs 29,385 ms)
+                                    // let str = unsafe {
+                                    //     std::str::from_utf8_unchecked(&buffer[i - 7..i - 2])
+                                    // };
+                                    // let rgba = u32::from_str_radix(str, 16).unwrap();
+
+                                    let rgba: u32 = (ASCII_HEXADECIMAL_VALUES
+                                        [buffer[i - 3] as usize]
+                                        as u32)
+                                        << 20
+                                        | (ASCII_HEXADECIMAL_VALUES[buffer[i - 2] as usize] as u32)
+                                            << 16
+                                        | (ASCII_HEXADECIMAL_VALUES[buffer[i - 5] as usize] as u32)
+                                            << 12
+                                        | (ASCII_HEXADECIMAL_VALUES[buffer[i - 4] as usize] as u32)
+                                            << 8
+                                        | (ASCII_HEXADECIMAL_VALUES[buffer[i - 7] as usize] as u32)
+                                            << 4
+                                        | (ASCII_HEXADECIMAL_VALUES[buffer[i - 6] as usize] as u32);
+
+                                    Self::set_pixel(fb, x, y, rgba);
+                                    pixels_drawn += 1;
+                                    if cfg!(feature = "count_pixels") {
+                                        // statistics.inc_pixels(ip);
+                                    }
+                                    continue;
+                                }
+
+                                // ... or must be followed by 8 bytes RGBA and newline
+                                if buffer[i + 8] == b'\n' {
+                                    bytes_parsed = i + 9;
+                                    i += 9; // We can advance one byte more than normal as we use continue and therefore not get incremented at the end of the loop
+
+                                    let rgba: u32 = (ASCII_HEXADECIMAL_VALUES
+                                        [buffer[i - 5] as usize]
+                                        as u32)
+                                        << 20
+                                        | (ASCII_HEXADECIMAL_VALUES[buffer[i - 4] as usize] as u32)
+                                            << 16
+                                        | (ASCII_HEXADECIMAL_VALUES[buffer[i - 7] as usize] as u32)
+                                            << 12
+                                        | (ASCII_HEXADECIMAL_VALUES[buffer[i - 6] as usize] as u32)
+                                            << 8
+                                        | (ASCII_HEXADECIMAL_VALUES[buffer[i - 9] as usize] as u32)
+                                            << 4
+                                        | (ASCII_HEXADECIMAL_VALUES[buffer[i - 8] as usize] as u32);
+                                    let alpha: u8 =
+                                        ASCII_HEXADECIMAL_VALUES[buffer[i - 3] as usize] << 4
+                                            | ASCII_HEXADECIMAL_VALUES[buffer[i - 2] as usize];
+
+                                    // Fully opaque and fully transparent pixels don't need any blending
+                                    match alpha {
+                                        0xff => Self::set_pixel(fb, x, y, rgba),
+                                        0x00 => (),
+                                        _ => fb.blend(x, y, rgba, alpha),
+                                    }
+                                    pixels_drawn += 1;
+                                    if cfg!(feature = "count_pixels") {
+                                        // statistics.inc_pixels(ip);
+                                    }
+
+                                    continue;
+                                }
+                            }
+
+                            // End of command to read Pixel value
+                            if buffer[i] == b'\n' {
+                                bytes_parsed = i + 1;
+                                i += 1;
+                                if let Some(rgb) = fb.get(x, y) {
+                                    match stream
+                                        .write_all(
+                                            format!(
+                                                "PX {} {} {:06x}\n",
+                                                // We don't want to return the actual (absolute) coordinates, the client should also get the result offseted
+                                                x - connection_x_offset,
+                                                y - connection_y_offset,
+                                                rgb.to_be() >> 8
+                                            )
+                                            .as_bytes(),
+                                        )
+                                        .await
+                                    {
+                                        Ok(_) => (),
+                                        Err(_) => continue,
+                                    }
+                                }
+                                continue;
+                            }
+                        }
+                    }
+                }
+            // Check for buffer[i] = "PB"
+            } else if unsafe { (buffer.as_ptr().add(i) as *const u16).read_unaligned() }
+                == 0x5042_u16.swap_bytes()
+            {
+                // As there is no newline terminating the binary command we need to check that we received all of it.
+                // If not, keep the bytes and process them in the next loop iteration
+                if i + BINARY_PIXEL_COMMAND_LENGTH > loop_end {
+                    bytes_parsed = i;
+                    break;
+                }
+
+                x = u16::from_le_bytes([buffer[i + 2], buffer[i + 3]]) as usize
+                    + connection_x_offset;
+                y = u16::from_le_bytes([buffer[i + 4], buffer[i + 5]]) as usize
+                    + connection_y_offset;
+                let rgba = u32::from_le_bytes([buffer[i + 6], buffer[i + 7], buffer[i + 8], 0]);
+                let alpha = buffer[i + 9];
+
+                i += BINARY_PIXEL_COMMAND_LENGTH;
+                bytes_parsed = i;
+
+                match alpha {
+                    0xff => Self::set_pixel(fb, x, y, rgba),
+                    0x00 => (),
+                    _ => fb.blend(x, y, rgba, alpha),
+                }
+                pixels_drawn += 1;
+                continue;
+            // Check for buffer[i] = "SIZE"
+            } else if unsafe { (buffer.as_ptr().add(i) as *const u32).read_unaligned() }
+                == 0x53495a45_u32.swap_bytes()
+            {
+                i += 4;
+                bytes_parsed = i;
+
+                stream
+                    .write_all(format!("SIZE {} {}\n", fb.get_width(), fb.get_height()).as_bytes())
+                    .await
+                    .expect("Failed to write bytes to tcp socket");
+                continue;
+            // Check for buffer[i] = "HELP"
+            } else if unsafe { (buffer.as_ptr().add(i) as *const u32).read_unaligned() }
+                == 0x48454c50_u32.swap_bytes()
+            {
+                i += 4;
+                bytes_parsed = i;
+
+                stream
+                    .write_all(HELP_TEXT)
+                    .await
+                    .expect("Failed to write bytes to tcp socket");
+                continue;
+            // Check for buffer[i] = "OFFSET "
+            } else if unsafe { (buffer.as_ptr().add(i) as *const u64).read_unaligned() }
+                & 0x00ff_ffff_ffff_ffff
+                == 0x4f46465345542000_u64.swap_bytes()
+            {
+                i += 7;
+                // Parse first x coordinate char
+                if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                    x = (buffer[i] - b'0') as usize;
+                    i += 1;
+
+                    // Parse optional second x coordinate char
+                    if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                        x = 10 * x + (buffer[i] - b'0') as usize;
+                        i += 1;
+
+                        // Parse optional third x coordinate char
+                        if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                            x = 10 * x + (buffer[i] - b'0') as usize;
+                            i += 1;
+
+                            // Parse optional forth x coordinate char
+                            if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                x = 10 * x + (buffer[i] - b'0') as usize;
+                                i += 1;
+
+                                // Parse optional fifth x coordinate char
+                                if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                    x = 10 * x + (buffer[i] - b'0') as usize;
+                                    i += 1;
+                                }
+                            }
+                        }
+                    }
+
+                    // Separator between x and y
+                    if buffer[i] == b' ' {
+                        i += 1;
+
+                        // Parse first y coordinate char
+                        if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                            y = (buffer[i] - b'0') as usize;
+                            i += 1;
+
+                            // Parse optional second y coordinate char
+                            if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                y = 10 * y + (buffer[i] - b'0') as usize;
+                                i += 1;
+
+                                // Parse optional third y coordinate char
+                                if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                    y = 10 * y + (buffer[i] - b'0') as usize;
+                                    i += 1;
+
+                                    // Parse optional forth y coordinate char
+                                    if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                        y = 10 * y + (buffer[i] - b'0') as usize;
+                                        i += 1;
+
+                                        // Parse optional fifth y coordinate char
+                                        if buffer[i] >= b'0' && buffer[i] <= b'9' {
+                                            y = 10 * y + (buffer[i] - b'0') as usize;
+                                            i += 1;
+                                        }
+                                    }
+                                }
+                            }
+
+                            // End of command to set offset
+                            if buffer[i] == b'\n' {
+                                bytes_parsed = i + 1;
+                                connection_x_offset = x;
+                                connection_y_offset = y;
+                                continue;
+                            }
+                        }
+                    }
+                }
+            // Check for buffer[i] = "RECT "
+            } else if unsafe { (buffer.as_ptr().add(i) as *const u64).read_unaligned() }
+                & 0x0000_00ff_ffff_ffff
+                == 0x5245435420000000_u64.swap_bytes()
+            {
+                if let Some((rect, newline)) = parse_rect_arguments(buffer, i + 5) {
+                    bytes_parsed = newline + 1;
+                    i = newline + 1;
+
+                    // Rectangles exceeding the allowed area are silently dropped, the same way pixels outside of the screen are
+                    if let Some(area) = rect
+                        .width
+                        .checked_mul(rect.height)
+                        .filter(|area| *area <= max_rect_area)
+                    {
+                        let x = rect.x + connection_x_offset;
+                        let y = rect.y + connection_y_offset;
+                        match rect.alpha {
+                            0xff => fb.fill_rect(x, y, rect.width, rect.height, rect.rgb),
+                            0x00 => (),
+                            _ => fb.blend_rect(x, y, rect.width, rect.height, rect.rgb, rect.alpha),
+                        }
+                        pixels_drawn += area as u64;
+                    }
+                    continue;
+                }
+            }
+
+            i += 1;
+        }
+
+        self.state = ParserState {
+            connection_x_offset,
+            connection_y_offset,
+            pixels_drawn,
+        };
+
+        bytes_parsed
+    }
+
+    fn state(&self) -> &ParserState {
+        &self.state
+    }
+
+    fn max_pixels_per_command(&self) -> usize {
+        self.max_rect_area.max(1)
+    }
+}
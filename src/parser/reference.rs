@@ -0,0 +1,276 @@
+use std::sync::Arc;
+
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    framebuffer::FrameBuffer,
+    parser::{
+        from_hex_char_map, parse_number, Parser, ParserState, BINARY_PIXEL_COMMAND_LENGTH,
+        HELP_TEXT, PARSER_LOOKAHEAD,
+    },
+};
+
+/// Straightforward parser that only uses bounds-checked accesses and is written to be easy to follow.
+/// It is much slower than the [`OriginalParser`](crate::parser::original::OriginalParser), but serves as
+/// reference when testing the optimized parsers. Because of this it intentionally behaves exactly like the
+/// optimized parsers, including the handling of malformed input.
+pub struct ReferenceParser {
+    fb: Arc<FrameBuffer>,
+    state: ParserState,
+    /// Maximum number of pixels a single RECT command is allowed to fill, 0 disables the RECT command
+    max_rect_area: usize,
+}
+
+impl ReferenceParser {
+    pub fn new(fb: Arc<FrameBuffer>, max_rect_area: usize) -> Self {
+        ReferenceParser {
+            fb,
+            state: ParserState::default(),
+            max_rect_area,
+        }
+    }
+
+    /// Parses the arguments of `PX x y[ rrggbb[aa]]\n`, `i` pointing behind "PX ".
+    /// Returns the index of the terminating newline.
+    ///
+    /// When the command is invalid, `i` is left behind the last character that could be parsed.
+    async fn parse_pixel(
+        &mut self,
+        buffer: &[u8],
+        i: &mut usize,
+        stream: &mut (impl AsyncWriteExt + Send + Unpin),
+    ) -> Option<usize> {
+        let x = parse_number(buffer, i)?;
+        if byte_at(buffer, *i) != b' ' {
+            return None;
+        }
+        *i += 1;
+        let y = parse_number(buffer, i)?;
+
+        let x = x + self.state.connection_x_offset;
+        let y = y + self.state.connection_y_offset;
+
+        if byte_at(buffer, *i) == b' ' {
+            *i += 1;
+            let color_start = *i;
+
+            if byte_at(buffer, color_start + 6) == b'\n' {
+                self.fb.set(x, y, parse_rgb(buffer, color_start));
+                self.state.pixels_drawn += 1;
+                return Some(color_start + 6);
+            }
+            if byte_at(buffer, color_start + 8) == b'\n' {
+                let rgb = parse_rgb(buffer, color_start);
+                match parse_hex_byte(buffer, color_start + 6) {
+                    0xff => self.fb.set(x, y, rgb),
+                    0x00 => (),
+                    alpha => self.fb.blend(x, y, rgb, alpha),
+                }
+                self.state.pixels_drawn += 1;
+                return Some(color_start + 8);
+            }
+        }
+
+        if byte_at(buffer, *i) == b'\n' {
+            if let Some(rgb) = self.fb.get(x, y) {
+                // Errors are ignored, as the next read from the socket will fail anyway
+                let _ = stream
+                    .write_all(
+                        format!(
+                            "PX {} {} {:06x}\n",
+                            x - self.state.connection_x_offset,
+                            y - self.state.connection_y_offset,
+                            rgb.to_be() >> 8
+                        )
+                        .as_bytes(),
+                    )
+                    .await;
+            }
+            return Some(*i);
+        }
+
+        None
+    }
+
+    /// Parses `PBxyrgba` starting at `buffer[i]`. The caller has to ensure the command was received completely.
+    fn parse_binary_pixel(&mut self, buffer: &[u8], i: usize) {
+        let command = &buffer[i..i + BINARY_PIXEL_COMMAND_LENGTH];
+        let x = u16::from_le_bytes([command[2], command[3]]) as usize;
+        let y = u16::from_le_bytes([command[4], command[5]]) as usize;
+        let rgb = u32::from_le_bytes([command[6], command[7], command[8], 0]);
+
+        let x = x + self.state.connection_x_offset;
+        let y = y + self.state.connection_y_offset;
+        match command[9] {
+            0xff => self.fb.set(x, y, rgb),
+            0x00 => (),
+            alpha => self.fb.blend(x, y, rgb, alpha),
+        }
+        self.state.pixels_drawn += 1;
+    }
+
+    /// Parses the arguments of `OFFSET x y\n`, `i` pointing behind "OFFSET ".
+    /// Returns the index of the terminating newline.
+    ///
+    /// When the command is invalid, `i` is left behind the last character that could be parsed.
+    fn parse_offset(&mut self, buffer: &[u8], i: &mut usize) -> Option<usize> {
+        let x = parse_number(buffer, i)?;
+        if byte_at(buffer, *i) != b' ' {
+            return None;
+        }
+        *i += 1;
+        let y = parse_number(buffer, i)?;
+        if byte_at(buffer, *i) != b'\n' {
+            return None;
+        }
+
+        self.state.connection_x_offset = x;
+        self.state.connection_y_offset = y;
+        Some(*i)
+    }
+
+    /// Parses the arguments of `RECT x y w h rrggbb[aa]\n`, `i` pointing behind "RECT ".
+    /// Returns the index of the terminating newline.
+    fn parse_rect(&mut self, buffer: &[u8], mut i: usize) -> Option<usize> {
+        let x = parse_argument(buffer, &mut i)?;
+        let y = parse_argument(buffer, &mut i)?;
+        let width = parse_argument(buffer, &mut i)?;
+        let height = parse_argument(buffer, &mut i)?;
+
+        let (rgb, alpha, newline) = if byte_at(buffer, i + 6) == b'\n' {
+            (parse_rgb(buffer, i), 0xff, i + 6)
+        } else if byte_at(buffer, i + 8) == b'\n' {
+            (parse_rgb(buffer, i), parse_hex_byte(buffer, i + 6), i + 8)
+        } else {
+            return None;
+        };
+
+        // Rectangles exceeding the allowed area are dropped
+        let Some(area) = width
+            .checked_mul(height)
+            .filter(|area| *area <= self.max_rect_area)
+        else {
+            return Some(newline);
+        };
+
+        let x = x + self.state.connection_x_offset;
+        let y = y + self.state.connection_y_offset;
+        match alpha {
+            0xff => self.fb.fill_rect(x, y, width, height, rgb),
+            0x00 => (),
+            alpha => self.fb.blend_rect(x, y, width, height, rgb, alpha),
+        }
+        self.state.pixels_drawn += area as u64;
+
+        Some(newline)
+    }
+}
+
+impl Parser for ReferenceParser {
+    async fn parse(
+        &mut self,
+        buffer: &[u8],
+        mut stream: impl AsyncWriteExt + Send + Unpin,
+    ) -> usize {
+        let data_end = buffer.len().saturating_sub(PARSER_LOOKAHEAD);
+        let mut bytes_parsed = 0;
+        let mut i = 0;
+
+        while i < data_end {
+            let remaining = &buffer[i..];
+
+            if remaining.starts_with(b"PX ") {
+                i += 3;
+                if let Some(newline) = self.parse_pixel(buffer, &mut i, &mut stream).await {
+                    bytes_parsed = newline + 1;
+                    i = newline + 1;
+                    continue;
+                }
+            } else if remaining.starts_with(b"PB") {
+                if i + BINARY_PIXEL_COMMAND_LENGTH > data_end {
+                    // Not received completely yet, keep the bytes for the next call
+                    bytes_parsed = i;
+                    break;
+                }
+                self.parse_binary_pixel(buffer, i);
+                i += BINARY_PIXEL_COMMAND_LENGTH;
+                bytes_parsed = i;
+                continue;
+            } else if remaining.starts_with(b"SIZE") {
+                stream
+                    .write_all(
+                        format!("SIZE {} {}\n", self.fb.get_width(), self.fb.get_height())
+                            .as_bytes(),
+                    )
+                    .await
+                    .expect("Failed to write bytes to tcp socket");
+                i += 4;
+                bytes_parsed = i;
+                continue;
+            } else if remaining.starts_with(b"HELP") {
+                stream
+                    .write_all(HELP_TEXT)
+                    .await
+                    .expect("Failed to write bytes to tcp socket");
+                i += 4;
+                bytes_parsed = i;
+                continue;
+            } else if remaining.starts_with(b"OFFSET ") {
+                i += 7;
+                if let Some(newline) = self.parse_offset(buffer, &mut i) {
+                    bytes_parsed = newline + 1;
+                    i = newline + 1;
+                    continue;
+                }
+            } else if remaining.starts_with(b"RECT ") {
+                if let Some(newline) = self.parse_rect(buffer, i + 5) {
+                    bytes_parsed = newline + 1;
+                    i = newline + 1;
+                    continue;
+                }
+            }
+
+            // Skip the character the parsing stopped at, this is the same thing the optimized parsers do
+            i += 1;
+        }
+
+        bytes_parsed
+    }
+
+    fn state(&self) -> &ParserState {
+        &self.state
+    }
+
+    fn max_pixels_per_command(&self) -> usize {
+        self.max_rect_area.max(1)
+    }
+}
+
+/// Returns the byte at the given index or 0 if the index is out of bounds (the same as the zeroed lookahead).
+fn byte_at(buffer: &[u8], i: usize) -> u8 {
+    buffer.get(i).copied().unwrap_or(0)
+}
+
+/// Parses a number followed by a space and advances `i` behind the space.
+fn parse_argument(buffer: &[u8], i: &mut usize) -> Option<usize> {
+    let number = parse_number(buffer, i)?;
+    if byte_at(buffer, *i) != b' ' {
+        return None;
+    }
+    *i += 1;
+    Some(number)
+}
+
+/// Parses two hexadecimal characters into a byte. Invalid characters are treated as 0.
+fn parse_hex_byte(buffer: &[u8], i: usize) -> u8 {
+    from_hex_char_map(byte_at(buffer, i)) << 4 | from_hex_char_map(byte_at(buffer, i + 1))
+}
+
+/// Parses `rrggbb` into the pixel format used by the [`FrameBuffer`].
+fn parse_rgb(buffer: &[u8], i: usize) -> u32 {
+    let red = parse_hex_byte(buffer, i) as u32;
+    let green = parse_hex_byte(buffer, i + 2) as u32;
+    let blue = parse_hex_byte(buffer, i + 4) as u32;
+
+    blue << 16 | green << 8 | red
+}
@@ -1,31 +1,44 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 use prometheus_exporter::{
     self,
-    prometheus::{register_int_gauge, register_int_gauge_vec, IntGauge, IntGaugeVec},
+    prometheus::{
+        register_int_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+        IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
+    },
 };
 use tokio::sync::broadcast;
 
-use crate::statistics::StatisticsInformationEvent;
+use crate::{
+    connection_limiter::ConnectionLimiter, sinks::SinkState, statistics::StatisticsInformationEvent,
+};
 
 pub struct PrometheusExporter {
     listen_addr: SocketAddr,
     statistics_information_rx: broadcast::Receiver<StatisticsInformationEvent>,
+    connection_limiter: Arc<ConnectionLimiter>,
 
     // Prometheus metrics
     metric_ips: IntGauge,
     metric_legacy_ips: IntGauge,
     metric_frame: IntGauge,
     metric_statistic_events: IntGauge,
+    metric_rejected_connections: IntCounter,
 
     metric_connections_for_ip: IntGaugeVec,
     metric_bytes_for_ip: IntGaugeVec,
+    metric_pixels_for_ip: IntGaugeVec,
+    metric_throttled_ms_for_ip: IntGaugeVec,
+
+    metric_sink_state: IntGaugeVec,
+    metric_sink_restarts: IntCounterVec,
 }
 
 impl PrometheusExporter {
     pub fn new(
         listen_addr: &str,
         statistics_information_rx: broadcast::Receiver<StatisticsInformationEvent>,
+        connection_limiter: Arc<ConnectionLimiter>,
     ) -> Self {
         let listen_addr = listen_addr.parse().unwrap_or_else(|_| {
             panic!("Failed to parse prometheus listen address: {listen_addr}",)
@@ -33,6 +46,7 @@ impl PrometheusExporter {
         PrometheusExporter {
             listen_addr,
             statistics_information_rx,
+            connection_limiter,
             metric_ips: register_int_gauge!("breakwater_ips", "Total number of IPs connected")
                 .unwrap(),
             metric_legacy_ips: register_int_gauge!(
@@ -47,6 +61,11 @@ impl PrometheusExporter {
                 "Number of statistics events send internally"
             )
             .unwrap(),
+            metric_rejected_connections: register_int_counter!(
+                "breakwater_rejected_connections_total",
+                "Number of connections rejected, e.g. because the IP had too many connections"
+            )
+            .unwrap(),
             metric_connections_for_ip: register_int_gauge_vec!(
                 "breakwater_connections",
                 "Number of client connections per IP address",
@@ -59,6 +78,30 @@ impl PrometheusExporter {
                 &["ip"]
             )
             .unwrap(),
+            metric_pixels_for_ip: register_int_gauge_vec!(
+                "breakwater_pixels",
+                "Number of pixels drawn",
+                &["ip"]
+            )
+            .unwrap(),
+            metric_throttled_ms_for_ip: register_int_gauge_vec!(
+                "breakwater_throttled_milliseconds",
+                "Time reads from the IP were delayed because it exceeded the rate limit",
+                &["ip"]
+            )
+            .unwrap(),
+            metric_sink_state: register_int_gauge_vec!(
+                "breakwater_sink_state",
+                "Current state of the sink, the gauge of the current state is 1, all others are 0",
+                &["sink", "state"]
+            )
+            .unwrap(),
+            metric_sink_restarts: register_int_counter_vec!(
+                "breakwater_sink_restarts_total",
+                "Number of times the sink got restarted after a failure",
+                &["sink"]
+            )
+            .unwrap(),
         }
     }
 
@@ -70,6 +113,12 @@ impl PrometheusExporter {
             self.metric_frame.set(event.frame as i64);
             self.metric_statistic_events
                 .set(event.statistic_events as i64);
+            // The limiter keeps the total, but counters can only be incremented
+            self.metric_rejected_connections.inc_by(
+                self.connection_limiter
+                    .rejected_connections()
+                    .saturating_sub(self.metric_rejected_connections.get()),
+            );
 
             // When clients drop a connection the item will be missing in `event.connections_for_ip,
             // but would stay forever in the Prometheus metric
@@ -88,6 +137,34 @@ impl PrometheusExporter {
                     .with_label_values(&[&ip.to_string()])
                     .set(*bytes as i64)
             });
+            self.metric_pixels_for_ip.reset();
+            event.pixels_for_ip.iter().for_each(|(ip, pixels)| {
+                self.metric_pixels_for_ip
+                    .with_label_values(&[&ip.to_string()])
+                    .set(*pixels as i64)
+            });
+            self.metric_throttled_ms_for_ip.reset();
+            event
+                .throttled_ms_for_ip
+                .iter()
+                .for_each(|(ip, throttled_ms)| {
+                    self.metric_throttled_ms_for_ip
+                        .with_label_values(&[&ip.to_string()])
+                        .set(*throttled_ms as i64)
+                });
+
+            event.sink_states.iter().for_each(|(sink, current_state)| {
+                for state in SinkState::ALL {
+                    self.metric_sink_state
+                        .with_label_values(&[sink, state.as_str()])
+                        .set((state == *current_state) as i64)
+                }
+            });
+            event.sink_restarts.iter().for_each(|(sink, restarts)| {
+                // The event contains the total, but counters can only be incremented
+                let metric = self.metric_sink_restarts.with_label_values(&[sink]);
+                metric.inc_by(restarts.saturating_sub(metric.get()));
+            });
         }
     }
 }
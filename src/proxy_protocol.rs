@@ -0,0 +1,172 @@
+//! Parsing of the [PROXY protocol](https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt) header, which load
+//! balancers such as HAProxy send at the start of every connection to tell us the address of the actual client.
+
+use std::{
+    io::{Error, ErrorKind},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    time::Duration,
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Proxies send the header right away, so clients that take longer are not behind a proxy
+pub const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// The longest possible v1 header, e.g. `PROXY TCP6 <ipv6> <ipv6> 65535 65535\r\n`
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Reads the PROXY protocol v1 or v2 header from the start of the stream, leaving the data following it untouched.
+///
+/// Returns the IP of the client, or [`None`] if the proxy didn't tell us, e.g. for health checks of the proxy itself.
+/// In this case the address of the proxy should be used. Streams without a valid header result in an error.
+pub async fn read_proxy_header(
+    stream: &mut (impl AsyncRead + Unpin),
+) -> std::io::Result<Option<IpAddr>> {
+    // Both versions are at least this long, so we can read this without consuming any data behind the header
+    let mut signature = [0u8; V2_SIGNATURE.len()];
+    stream.read_exact(&mut signature).await?;
+
+    if &signature == V2_SIGNATURE {
+        read_v2_header(stream).await
+    } else if signature.starts_with(V1_PREFIX) {
+        read_v1_header(stream, &signature).await
+    } else {
+        Err(invalid_header("Missing PROXY protocol signature"))
+    }
+}
+
+/// Reads the rest of a v1 header such as `PROXY TCP4 192.0.2.1 198.51.100.1 56324 1234\r\n`.
+async fn read_v1_header(
+    stream: &mut (impl AsyncRead + Unpin),
+    start: &[u8],
+) -> std::io::Result<Option<IpAddr>> {
+    let mut header = start.to_vec();
+    // We must not read past the header, so we need to read byte by byte. This only happens once per connection.
+    while !header.ends_with(b"\r\n") {
+        if header.len() >= V1_MAX_LENGTH {
+            return Err(invalid_header("PROXY protocol v1 header too long"));
+        }
+        header.push(stream.read_u8().await?);
+    }
+
+    let header = std::str::from_utf8(&header[V1_PREFIX.len()..header.len() - 2])
+        .map_err(|_| invalid_header("PROXY protocol v1 header is not valid UTF-8"))?;
+    let mut fields = header.split(' ');
+    match fields.next() {
+        Some("TCP4" | "TCP6") => {
+            let ip = fields
+                .next()
+                .and_then(|ip| ip.parse().ok())
+                .ok_or_else(|| {
+                    invalid_header("Invalid source address in PROXY protocol v1 header")
+                })?;
+            Ok(Some(ip))
+        }
+        // The proxy doesn't know the client, e.g. for its own health checks
+        Some("UNKNOWN") => Ok(None),
+        _ => Err(invalid_header(
+            "Unsupported protocol in PROXY protocol v1 header",
+        )),
+    }
+}
+
+/// Reads the rest of a v2 header following the signature.
+async fn read_v2_header(stream: &mut (impl AsyncRead + Unpin)) -> std::io::Result<Option<IpAddr>> {
+    let version_command = stream.read_u8().await?;
+    let family_protocol = stream.read_u8().await?;
+    let length = stream.read_u16().await? as usize;
+    // Addresses followed by optional TLVs, which we don't need but have to consume
+    let mut addresses = vec![0u8; length];
+    stream.read_exact(&mut addresses).await?;
+
+    if version_command >> 4 != 2 {
+        return Err(invalid_header("Unsupported PROXY protocol version"));
+    }
+    match version_command & 0x0f {
+        // LOCAL: The connection was established by the proxy itself, e.g. for health checks
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => (),
+        _ => return Err(invalid_header("Unsupported PROXY protocol v2 command")),
+    }
+
+    // The high nibble is the address family, the low nibble the transport protocol
+    match family_protocol >> 4 {
+        // AF_INET: source address, destination address, source port, destination port
+        0x1 if addresses.len() >= 12 => Ok(Some(IpAddr::V4(Ipv4Addr::from(
+            <[u8; 4]>::try_from(&addresses[..4]).unwrap(),
+        )))),
+        // AF_INET6
+        0x2 if addresses.len() >= 36 => Ok(Some(IpAddr::V6(Ipv6Addr::from(
+            <[u8; 16]>::try_from(&addresses[..16]).unwrap(),
+        )))),
+        0x1 | 0x2 => Err(invalid_header("PROXY protocol v2 addresses too short")),
+        // AF_UNSPEC or AF_UNIX, which don't give us an IP
+        _ => Ok(None),
+    }
+}
+
+fn invalid_header(reason: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 1234\r\n", Some("192.0.2.1"))]
+    #[case(
+        b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 1234\r\n",
+        Some("2001:db8::1")
+    )]
+    #[case(b"PROXY UNKNOWN\r\n", None)]
+    #[case(b"PROXY UNKNOWN 2001:db8::1 2001:db8::2 56324 1234\r\n", None)]
+    // v2 PROXY over TCP4 without TLVs
+    #[case(
+        b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c\xc0\x00\x02\x01\xc6\x33\x64\x01\xdc\x04\x04\xd2",
+        Some("192.0.2.1")
+    )]
+    // v2 PROXY over TCP6 with a NOOP TLV
+    #[case(
+        b"\r\n\r\n\0\r\nQUIT\n\x21\x21\x00\x27\
+          \x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\
+          \x20\x01\x0d\xb8\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x02\
+          \xdc\x04\x04\xd2\x04\x00\x00",
+        Some("2001:db8::1")
+    )]
+    // v2 LOCAL
+    #[case(b"\r\n\r\n\0\r\nQUIT\n\x20\x00\x00\x00", None)]
+    #[tokio::test]
+    async fn test_read_proxy_header(#[case] header: &[u8], #[case] expected: Option<&str>) {
+        let data = [header, b"PX 0 0 ffffff\n"].concat();
+        let mut stream = data.as_slice();
+
+        let ip = read_proxy_header(&mut stream).await.unwrap();
+        assert_eq!(ip, expected.map(|ip| ip.parse().unwrap()));
+        // The Pixelflut commands following the header are left for the parser
+        assert_eq!(stream, b"PX 0 0 ffffff\n");
+    }
+
+    #[rstest]
+    #[case(b"PX 0 0 ffffff\nPX 1 1 ffffff\n")]
+    #[case(b"PROXY TCP4 not_an_ip 198.51.100.1 56324 1234\r\n")]
+    #[case(b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 1234\r\n")]
+    // Missing \r\n
+    #[case(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 1234 PX 0 0 ffffff\nPX 0 0 ffffff\nPX 0 0 ffffff\nPX 0 0 ffffff\n")]
+    // Unsupported version
+    #[case(b"\r\n\r\n\0\r\nQUIT\n\x11\x11\x00\x0c\xc0\x00\x02\x01\xc6\x33\x64\x01\xdc\x04\x04\xd2")]
+    // Addresses too short
+    #[case(b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x04\xc0\x00\x02\x01")]
+    // Truncated
+    #[case(b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c\xc0\x00")]
+    #[case(b"PROXY")]
+    #[tokio::test]
+    async fn test_invalid_proxy_header(#[case] data: &[u8]) {
+        let mut stream = data;
+        assert!(read_proxy_header(&mut stream).await.is_err());
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use clap::ValueEnum;
+use log::{info, warn};
+
+use crate::connection_limiter::ipv6_prefix;
+
+/// How often the file containing the limit is checked for changes
+const LIMIT_FILE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What the rate limit is counted in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum RateLimitUnit {
+    /// Pixels drawn by the client, RECT commands count with their full area
+    #[default]
+    Pixels,
+    /// Bytes received from the client
+    Bytes,
+}
+
+/// Limits the rate at which clients can draw, so that a few clients with a fast uplink can't take over the whole canvas.
+///
+/// Every client (an IP address, or an IPv6 prefix as a single host usually has a whole prefix at hand) gets a token bucket
+/// shared by all of its connections. The bucket refills with the limit per second and holds at most one second worth of
+/// tokens. Clients exceeding the limit are not disconnected, instead we stop reading from their connections until the
+/// bucket has recovered, so that TCP pushes back on them.
+#[derive(Debug)]
+pub struct RateLimiter {
+    unit: RateLimitUnit,
+    /// Tokens per second, 0 disables the limit. Can be changed at runtime.
+    limit: AtomicU64,
+    ipv6_prefix_length: u8,
+
+    /// Buckets are dropped together with the last connection of the client, so we only keep weak references here
+    buckets: Mutex<HashMap<IpAddr, Weak<Mutex<TokenBucket>>>>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    /// Goes negative when the client used more than it had, which it has to pay back by waiting
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// The token bucket of a single client, should be kept as long as the connection is open.
+#[derive(Debug)]
+pub struct RateLimitBucket {
+    limiter: Arc<RateLimiter>,
+    bucket: Arc<Mutex<TokenBucket>>,
+}
+
+impl RateLimiter {
+    /// Creates a new limiter allowing `limit` [`RateLimitUnit`]s per second and client, 0 disables the limit.
+    /// The IP needs to be canonical, so that IPv4 addresses mapped into IPv6 are limited as IPv4 addresses.
+    pub fn new(unit: RateLimitUnit, limit: u64, ipv6_prefix_length: u8) -> Self {
+        assert!(
+            ipv6_prefix_length <= 128,
+            "IPv6 prefix length must be at most 128"
+        );
+        RateLimiter {
+            unit,
+            limit: AtomicU64::new(limit),
+            ipv6_prefix_length,
+            buckets: Mutex::default(),
+        }
+    }
+
+    /// Returns a limiter that never throttles.
+    pub fn unlimited() -> Self {
+        RateLimiter::new(RateLimitUnit::default(), 0, 64)
+    }
+
+    pub fn unit(&self) -> RateLimitUnit {
+        self.unit
+    }
+
+    /// Returns the current limit per second and client, 0 means unlimited.
+    pub fn limit(&self) -> u64 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Changes the limit per second and client for all existing and future connections, 0 disables the limit.
+    pub fn set_limit(&self, limit: u64) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Returns the token bucket shared by all connections of the client the IP belongs to.
+    pub fn bucket(self: &Arc<Self>, ip: IpAddr) -> RateLimitBucket {
+        let key = match ip {
+            IpAddr::V4(_) => ip,
+            IpAddr::V6(v6) => IpAddr::V6(ipv6_prefix(v6, self.ipv6_prefix_length)),
+        };
+
+        let mut buckets = self.buckets.lock().expect("Rate limiter poisoned");
+        let bucket = match buckets.get(&key).and_then(Weak::upgrade) {
+            Some(bucket) => bucket,
+            None => {
+                // Clean up the buckets of clients that are gone, so that the map doesn't grow forever
+                buckets.retain(|_, bucket| bucket.strong_count() > 0);
+
+                let bucket = Arc::new(Mutex::new(TokenBucket {
+                    tokens: self.limit() as f64,
+                    last_refill: Instant::now(),
+                }));
+                buckets.insert(key, Arc::downgrade(&bucket));
+                bucket
+            }
+        };
+
+        RateLimitBucket {
+            limiter: Arc::clone(self),
+            bucket,
+        }
+    }
+
+    /// Keeps the limit in sync with the given file, which contains the limit as a single number (0 disables the limit).
+    /// Invalid contents are logged and ignored, so the previous limit stays in place. Never returns.
+    pub async fn follow_limit_file(&self, path: &Path) {
+        let mut last_modified: Option<SystemTime> = None;
+        let mut missing_reported = false;
+
+        loop {
+            match tokio::fs::metadata(path).await.and_then(|m| m.modified()) {
+                Ok(modified) if last_modified != Some(modified) => {
+                    last_modified = Some(modified);
+                    missing_reported = false;
+                    match read_limit_file(path).await {
+                        Ok(limit) if limit != self.limit() => {
+                            info!("Changing rate limit to {limit} per second (0 is unlimited)");
+                            self.set_limit(limit);
+                        }
+                        Ok(_) => (),
+                        Err(err) => warn!(
+                            "Failed to read rate limit from {}, keeping the current limit: {err}",
+                            path.display()
+                        ),
+                    }
+                }
+                Ok(_) => (),
+                Err(err) => {
+                    if !missing_reported {
+                        warn!(
+                            "Failed to read rate limit from {}, keeping the current limit: {err}",
+                            path.display()
+                        );
+                        missing_reported = true;
+                    }
+                    // Re-read the file once it shows up again, even if it has the same modification time
+                    last_modified = None;
+                }
+            }
+
+            tokio::time::sleep(LIMIT_FILE_POLL_INTERVAL).await;
+        }
+    }
+}
+
+async fn read_limit_file(path: &Path) -> std::io::Result<u64> {
+    let content = tokio::fs::read_to_string(path).await?;
+    content.trim().parse().map_err(|err| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Invalid rate limit {:?}: {err}", content.trim()),
+        )
+    })
+}
+
+impl RateLimitBucket {
+    /// Takes the tokens out of the bucket and returns for how long the client has to be throttled.
+    pub fn consume(&self, tokens: u64) -> Duration {
+        let limit = self.limiter.limit();
+        if limit == 0 {
+            return Duration::ZERO;
+        }
+
+        let limit = limit as f64;
+        let mut bucket = self.bucket.lock().expect("Rate limit bucket poisoned");
+        bucket.refill(limit);
+        bucket.tokens -= tokens as f64;
+
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / limit)
+        }
+    }
+
+    /// Returns the number of tokens the client can use right now, or [`None`] if there is no limit.
+    pub fn available(&self) -> Option<u64> {
+        let limit = self.limiter.limit();
+        if limit == 0 {
+            return None;
+        }
+
+        let mut bucket = self.bucket.lock().expect("Rate limit bucket poisoned");
+        bucket.refill(limit as f64);
+        Some(bucket.tokens.max(0.0) as u64)
+    }
+
+    /// Returns whether the bucket is full again. Dropping a full bucket loses nothing, as new buckets start full.
+    pub fn is_full(&self) -> bool {
+        let limit = self.limiter.limit();
+        if limit == 0 {
+            return true;
+        }
+
+        let mut bucket = self.bucket.lock().expect("Rate limit bucket poisoned");
+        bucket.refill(limit as f64);
+        bucket.tokens >= limit as f64
+    }
+}
+
+impl TokenBucket {
+    fn refill(&mut self, limit: f64) {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * limit;
+        // The bucket holds at most one second worth of tokens, this also applies when the limit got lowered
+        self.tokens = (self.tokens + refill).min(limit);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[test]
+    fn test_burst_and_throttling() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitUnit::Bytes, 1000, 64));
+        let bucket = limiter.bucket("10.0.0.1".parse().unwrap());
+
+        // One second worth of tokens can be used right away
+        assert_eq!(bucket.consume(1000), Duration::ZERO);
+        // Everything above needs to be paid back
+        let throttled = bucket.consume(500);
+        assert!(
+            throttled > Duration::from_millis(450) && throttled <= Duration::from_millis(500),
+            "{throttled:?}"
+        );
+    }
+
+    #[test]
+    fn test_available_tokens() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitUnit::Bytes, 1000, 64));
+        let bucket = limiter.bucket("10.0.0.1".parse().unwrap());
+        assert_eq!(bucket.available(), Some(1000));
+        assert!(bucket.is_full());
+
+        bucket.consume(1500);
+        assert_eq!(bucket.available(), Some(0));
+        assert!(!bucket.is_full());
+
+        limiter.set_limit(0);
+        assert_eq!(bucket.available(), None);
+    }
+
+    #[rstest]
+    // Same /64
+    #[case("2001:db8::1", "2001:db8::2", 64, true)]
+    // Different /64
+    #[case("2001:db8:0:1::1", "2001:db8::2", 64, false)]
+    #[case("2001:db8::1", "2001:db8::2", 128, false)]
+    #[case("10.0.0.1", "10.0.0.1", 64, true)]
+    #[case("10.0.0.1", "10.0.0.2", 0, false)]
+    fn test_bucket_is_shared(
+        #[case] first_ip: IpAddr,
+        #[case] second_ip: IpAddr,
+        #[case] ipv6_prefix_length: u8,
+        #[case] expected_shared: bool,
+    ) {
+        let limiter = Arc::new(RateLimiter::new(
+            RateLimitUnit::Pixels,
+            100,
+            ipv6_prefix_length,
+        ));
+        let first = limiter.bucket(first_ip);
+        let second = limiter.bucket(second_ip);
+
+        assert_eq!(first.consume(100), Duration::ZERO);
+        assert_eq!(second.consume(50).is_zero(), !expected_shared);
+    }
+
+    #[test]
+    fn test_change_limit_at_runtime() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitUnit::Bytes, 0, 64));
+        let bucket = limiter.bucket("10.0.0.1".parse().unwrap());
+        assert_eq!(bucket.consume(u32::MAX as u64), Duration::ZERO);
+
+        limiter.set_limit(100);
+        assert!(!bucket.consume(200).is_zero());
+
+        limiter.set_limit(0);
+        assert_eq!(bucket.consume(u32::MAX as u64), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_buckets_of_closed_connections_are_removed() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitUnit::Bytes, 100, 64));
+        for i in 0..100 {
+            let bucket = limiter.bucket(IpAddr::from([10, 0, 0, i]));
+            bucket.consume(1);
+        }
+        let _bucket = limiter.bucket("10.0.1.0".parse().unwrap());
+
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_follow_limit_file() {
+        let path =
+            std::env::temp_dir().join(format!("breakwater_test_{}_rate_limit", std::process::id()));
+        std::fs::write(&path, "1234\n").unwrap();
+        let limiter = Arc::new(RateLimiter::new(RateLimitUnit::Pixels, 0, 64));
+
+        let following_limiter = Arc::clone(&limiter);
+        let following_path = path.clone();
+        let follower =
+            tokio::spawn(async move { following_limiter.follow_limit_file(&following_path).await });
+
+        wait_for_limit(&limiter, 1234).await;
+        std::fs::write(&path, "not a number").unwrap();
+        tokio::time::sleep(LIMIT_FILE_POLL_INTERVAL * 2).await;
+        assert_eq!(limiter.limit(), 1234);
+
+        follower.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    async fn wait_for_limit(limiter: &RateLimiter, expected: u64) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while limiter.limit() != expected {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Limit did not get changed");
+    }
+}
@@ -1,132 +1,700 @@
-use std::{process::Stdio, sync::Arc, time::Duration};
+use std::{
+    cmp::min,
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::{Duration, Instant, SystemTime},
+};
 
-use chrono::Local;
-use tokio::{io::AsyncWriteExt, process::Command, time};
+use chrono::format::{Parsed, StrftimeItems};
+use clap::ValueEnum;
+use log::{debug, error, info, warn, Level};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    process::{ChildStderr, Command},
+    task, time,
+};
 
-use crate::{args::Args, framebuffer::FrameBuffer};
+use crate::{
+    args::Args,
+    sinks::{Sink, SinkContext, SinkFuture, SinkState},
+    statistics::StatisticsEvent,
+};
+
+/// Time to wait before restarting ffmpeg for the first time
+const INITIAL_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+/// The backoff doubles with every failed restart until it reaches this
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(60);
+/// If ffmpeg ran for this long it's considered healthy and the backoff starts from the beginning again
+const HEALTHY_RUN_DURATION: Duration = Duration::from_secs(60);
+/// How often the disk usage of the recordings is checked
+const DISK_USAGE_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
 pub struct FfmpegSink {
-    fb: Arc<FrameBuffer>,
+    context: SinkContext,
     rtmp_address: Option<String>,
-    save_video_to_file: bool,
+    recording: Option<Recording>,
+    web_stream: Option<WebStream>,
     fps: u32,
+    encoding: FfmpegEncoding,
+}
+
+/// Settings of the encoder, which are shared by all outputs.
+#[derive(Clone, Debug)]
+pub struct FfmpegEncoding {
+    pub video_codec: String,
+    pub preset: String,
+    pub video_bitrate: String,
+    /// Number of frames between two keyframes, defaults to two seconds
+    pub gop_size: Option<u32>,
+    /// Scale the video to this (width, height) instead of using the size of the framebuffer
+    pub output_resolution: Option<(u32, u32)>,
+    pub threads: u32,
+    /// Add a silent audio track, as some streaming platforms refuse streams without audio
+    pub audio: bool,
+    /// Passed to ffmpeg as is, right before the output
+    pub extra_args: Vec<String>,
+}
+
+impl FfmpegEncoding {
+    pub fn new(args: &Args) -> Self {
+        FfmpegEncoding {
+            video_codec: args.ffmpeg_video_codec.clone(),
+            preset: args.ffmpeg_preset.clone(),
+            video_bitrate: args.ffmpeg_video_bitrate.clone(),
+            gop_size: args.ffmpeg_gop_size,
+            output_resolution: args.ffmpeg_output_resolution,
+            threads: args.ffmpeg_threads,
+            audio: !args.ffmpeg_disable_audio,
+            extra_args: args
+                .ffmpeg_extra_args
+                .as_deref()
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+        }
+    }
+}
+
+impl Sink for FfmpegSink {
+    fn name(&self) -> &str {
+        "ffmpeg"
+    }
+
+    fn run(&self) -> SinkFuture<'_> {
+        Box::pin(async {
+            tokio::select! {
+                result = self.run_ffmpeg() => result,
+                result = self.limit_recordings_disk_usage() => result,
+            }
+        })
+    }
 }
 
 impl FfmpegSink {
-    pub fn new(args: &Args, fb: Arc<FrameBuffer>) -> Option<Self> {
-        if args.rtmp_address.is_some() || args.save_video_to_file {
+    pub fn new(args: &Args, context: &SinkContext) -> Option<Self> {
+        let web_stream = args
+            .web_stream_directory
+            .as_ref()
+            .map(|directory| WebStream {
+                directory: PathBuf::from(directory),
+                format: args.web_stream_format,
+                segment_duration_s: args.web_stream_segment_duration_s,
+                playlist_size: args.web_stream_playlist_size,
+            });
+
+        let recording = args.save_video_to_file.then(|| Recording {
+            directory: PathBuf::from(&args.video_directory),
+            file_pattern: args.video_file_pattern.clone(),
+            segment_duration_s: args.video_segment_duration_s,
+            max_disk_usage_bytes: args.video_max_disk_usage_mb.map(|mb| mb * 1024 * 1024),
+        });
+
+        if args.rtmp_address.is_some() || recording.is_some() || web_stream.is_some() {
             Some(FfmpegSink {
-                fb,
+                context: context.clone(),
                 rtmp_address: args.rtmp_address.clone(),
-                save_video_to_file: args.save_video_to_file,
+                recording,
+                web_stream,
                 fps: args.fps,
+                encoding: FfmpegEncoding::new(args),
             })
         } else {
             None
         }
     }
 
-    pub async fn run(&self) -> tokio::io::Result<()> {
-        let mut ffmpeg_args: Vec<String> = self
-            .ffmpeg_input_args()
-            .into_iter()
-            .flat_map(|(arg, value)| [format!("-{arg}"), value])
-            .collect();
-
-        let video_file = format!(
-            "pixelflut_dump_{}.mp4",
-            Local::now().format("%Y-%m-%d_%H-%M-%S")
-        );
-        match &self.rtmp_address {
-            Some(rtmp_address) => {
-                if self.save_video_to_file {
-                    ffmpeg_args.extend(
-                        self.ffmpeg_rtmp_sink_args()
-                            .into_iter()
-                            .flat_map(|(arg, value)| [format!("-{arg}"), value])
-                            .collect::<Vec<_>>(),
-                    );
-                    ffmpeg_args.extend([
-                        "-f".to_string(),
-                        "tee".to_string(),
-                        "-map".to_string(),
-                        "0:v".to_string(),
-                        "-map".to_string(),
-                        "1:a".to_string(),
-                        format!(
-                            "{video_file}|[f=flv]{rtmp_address}",
-                            rtmp_address = rtmp_address.clone(),
-                        ),
-                    ]);
-                    todo!("Writing to file and rtmp sink simultaneously currently not supported");
-                } else {
-                    ffmpeg_args.extend(
-                        self.ffmpeg_rtmp_sink_args()
-                            .into_iter()
-                            .flat_map(|(arg, value)| [format!("-{arg}"), value])
-                            .collect::<Vec<_>>(),
-                    );
-                    ffmpeg_args.extend(["-f".to_string(), "flv".to_string(), rtmp_address.clone()])
-                }
+    /// Runs ffmpeg and restarts it with an exponential backoff whenever it stops, e.g. because the rtmp server
+    /// restarted.
+    async fn run_ffmpeg(&self) -> std::io::Result<()> {
+        let mut backoff = INITIAL_RESTART_BACKOFF;
+        loop {
+            let start = Instant::now();
+            let err = self.run_ffmpeg_once().await;
+            if start.elapsed() >= HEALTHY_RUN_DURATION {
+                backoff = INITIAL_RESTART_BACKOFF;
             }
-            None => {
-                if self.save_video_to_file {
-                    ffmpeg_args.extend([video_file])
-                } else {
-                    unreachable!("FfmpegSink can only be created when either rtmp or video file is activated")
-                }
+
+            error!("ffmpeg stopped: {err}. Restarting it in {backoff:?}");
+            self.context
+                .report_sink_state(self.name(), SinkState::Restarting)
+                .await;
+            time::sleep(backoff).await;
+            backoff = min(backoff * 2, MAX_RESTART_BACKOFF);
+
+            // Nobody might be interested in the statistics
+            let _ = self
+                .context
+                .statistics_tx
+                .send(StatisticsEvent::SinkRestarted {
+                    sink: self.name().to_string(),
+                })
+                .await;
+            self.context
+                .report_sink_state(self.name(), SinkState::Running)
+                .await;
+        }
+    }
+
+    /// Starts ffmpeg and feeds it frames until it stops. Returns the reason it stopped.
+    async fn run_ffmpeg_once(&self) -> std::io::Error {
+        let directories = [
+            self.recording
+                .as_ref()
+                .map(|recording| &recording.directory),
+            self.web_stream
+                .as_ref()
+                .map(|web_stream| &web_stream.directory),
+        ];
+        for directory in directories.into_iter().flatten() {
+            if let Err(err) = tokio::fs::create_dir_all(directory).await {
+                return std::io::Error::new(
+                    err.kind(),
+                    format!("Failed to create directory {}: {err}", directory.display()),
+                );
             }
         }
 
-        log::info!("ffmpeg {}", ffmpeg_args.join(" "));
-        let mut command = Command::new("ffmpeg")
+        let ffmpeg_args = self.ffmpeg_args();
+
+        info!("ffmpeg {}", ffmpeg_args.join(" "));
+        let mut command = match Command::new("ffmpeg")
             .args(ffmpeg_args)
             .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            // Otherwise ffmpeg would keep running if the sink gets dropped, e.g. on shutdown
+            .kill_on_drop(true)
             .spawn()
-            .unwrap();
+        {
+            Ok(command) => command,
+            Err(err) => {
+                return std::io::Error::new(err.kind(), format!("Failed to start ffmpeg: {err}"))
+            }
+        };
 
         let mut stdin = command
             .stdin
             .take()
             .expect("child did not have a handle to stdin");
+        let stderr = command
+            .stderr
+            .take()
+            .expect("child did not have a handle to stderr");
+        tokio::spawn(log_ffmpeg_output(stderr));
+
+        let fb = &self.context.fb;
+        let mut interval = time::interval(Duration::from_micros(1_000_000 / self.fps as u64));
+        let mut pixels = vec![0; fb.get_size()];
+        let mut frame = Vec::with_capacity(fb.get_size() * 4);
+        let err = loop {
+            fb.copy_to(&mut pixels);
+            frame.clear();
+            frame.extend(pixels.iter().flat_map(|pixel| pixel.to_ne_bytes()));
+            if let Err(err) = stdin.write_all(&frame).await {
+                break err;
+            }
+            interval.tick().await;
+        };
+
+        // Writing most likely failed because ffmpeg exited, so its exit status is more helpful
+        drop(stdin);
+        match command.wait().await {
+            Ok(status) => std::io::Error::other(format!("ffmpeg exited with {status}")),
+            Err(_) => err,
+        }
+    }
+
+    fn ffmpeg_args(&self) -> Vec<String> {
+        // Prefix every line with the log level, so that we can forward it to our log with the correct level
+        let mut ffmpeg_args: Vec<String> = ["-hide_banner", "-nostats", "-loglevel", "level+info"]
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        ffmpeg_args.extend(
+            self.ffmpeg_input_args()
+                .into_iter()
+                .chain(self.ffmpeg_encoding_args())
+                .flat_map(|(arg, value)| [format!("-{arg}"), value]),
+        );
+        ffmpeg_args.extend(self.encoding.extra_args.iter().cloned());
+
+        let outputs = self.ffmpeg_outputs();
+        match outputs.as_slice() {
+            [] => unreachable!(
+                "FfmpegSink can only be created when either rtmp, video file or web stream is activated"
+            ),
+            [output] => {
+                if let Some(format) = output.format {
+                    ffmpeg_args.extend(["-f".to_string(), format.to_string()]);
+                }
+                ffmpeg_args.extend(
+                    output
+                        .options
+                        .iter()
+                        .flat_map(|(option, value)| [format!("-{option}"), value.clone()]),
+                );
+                ffmpeg_args.push(output.target.clone());
+            }
+            outputs => {
+                // The tee muxer encodes the stream once and writes it to all outputs.
+                // mp4 needs the codec headers up front, which we only get with global headers.
+                ffmpeg_args.extend([
+                    "-flags".to_string(),
+                    "+global_header".to_string(),
+                    "-map".to_string(),
+                    "0:v".to_string(),
+                ]);
+                if self.encoding.audio {
+                    ffmpeg_args.extend(["-map".to_string(), "1:a".to_string()]);
+                }
+                ffmpeg_args.extend([
+                    "-f".to_string(),
+                    "tee".to_string(),
+                    outputs
+                        .iter()
+                        .map(FfmpegOutput::tee_slave)
+                        .collect::<Vec<_>>()
+                        .join("|"),
+                ]);
+            }
+        }
+
+        ffmpeg_args
+    }
 
-        let mut interval = time::interval(Duration::from_micros(1_000_000 / 30));
+    /// Periodically deletes the oldest recordings, so that they don't use more than the configured disk space.
+    async fn limit_recordings_disk_usage(&self) -> std::io::Result<()> {
+        let Some(recording) = &self.recording else {
+            return std::future::pending().await;
+        };
+        let Some(max_disk_usage_bytes) = recording.max_disk_usage_bytes else {
+            return std::future::pending().await;
+        };
+
+        let mut interval = time::interval(DISK_USAGE_CHECK_INTERVAL);
         loop {
-            let bytes = self.fb.as_bytes();
-            stdin.write_all(bytes).await?;
             interval.tick().await;
+            // Listing and deleting files blocks, which must not happen on the async runtime
+            let blocking_recording = recording.clone();
+            let result = task::spawn_blocking(move || {
+                let segments = blocking_recording.list_segments()?;
+                remove_oldest_segments(&segments, max_disk_usage_bytes);
+                Ok::<_, std::io::Error>(())
+            })
+            .await
+            .expect("Failed to limit the disk usage of the recordings");
+            match result {
+                Ok(()) => (),
+                // The directory is created once ffmpeg starts
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+                Err(err) => warn!(
+                    "Failed to list recordings in {}: {err}",
+                    recording.directory.display()
+                ),
+            }
+        }
+    }
+
+    fn ffmpeg_outputs(&self) -> Vec<FfmpegOutput> {
+        let mut outputs = Vec::new();
+        if let Some(recording) = &self.recording {
+            outputs.push(recording.output());
+        }
+        if let Some(web_stream) = &self.web_stream {
+            outputs.push(web_stream.output());
+        }
+        if let Some(rtmp_address) = &self.rtmp_address {
+            outputs.push(FfmpegOutput {
+                format: Some("flv"),
+                options: Vec::new(),
+                target: rtmp_address.clone(),
+                // If the rtmp server goes away we want to keep writing the other outputs
+                ignore_failure: true,
+            });
         }
+
+        outputs
     }
 
     fn ffmpeg_input_args(&self) -> Vec<(String, String)> {
-        let video_size: String = format!("{}x{}", self.fb.get_width(), self.fb.get_height());
-        [
-            ("f", "rawvideo"),
-            ("pixel_format", "rgb0"),
-            ("video_size", video_size.as_str()),
-            ("i", "-"),
-            ("f", "lavfi"),
-            ("i", "anullsrc=channel_layout=stereo:sample_rate=44100"),
-        ]
-        .map(|(s1, s2)| (s1.to_string(), s2.to_string()))
-        .into()
-    }
-
-    fn ffmpeg_rtmp_sink_args(&self) -> Vec<(String, String)> {
-        [
-            ("vcodec", "libx264"),
-            ("acodec", "aac"),
-            ("pix_fmt", "yuv420p"),
-            ("preset", "veryfast"),
-            ("r", self.fps.to_string().as_str()),
-            ("g", (self.fps * 2).to_string().as_str()),
-            ("ar", "44100"),
-            ("b:v", "4500k"),
-            ("b:a", "128k"),
-            ("threads", "4"),
-            // ("f", "flv"),
-        ]
-        .map(|(s1, s2)| (s1.to_string(), s2.to_string()))
-        .into()
+        let fb = &self.context.fb;
+        let video_size: String = format!("{}x{}", fb.get_width(), fb.get_height());
+        let mut args = vec![
+            ("f", "rawvideo".to_string()),
+            ("pixel_format", "rgb0".to_string()),
+            ("video_size", video_size),
+            // Otherwise ffmpeg assumes 25 fps for raw video
+            ("framerate", self.fps.to_string()),
+            ("i", "-".to_string()),
+        ];
+        if self.encoding.audio {
+            args.extend([
+                ("f", "lavfi".to_string()),
+                (
+                    "i",
+                    "anullsrc=channel_layout=stereo:sample_rate=44100".to_string(),
+                ),
+            ]);
+        }
+
+        args.into_iter()
+            .map(|(arg, value)| (arg.to_string(), value))
+            .collect()
+    }
+
+    fn ffmpeg_encoding_args(&self) -> Vec<(String, String)> {
+        let encoding = &self.encoding;
+        let mut args = vec![
+            ("vcodec", encoding.video_codec.clone()),
+            ("pix_fmt", "yuv420p".to_string()),
+            ("preset", encoding.preset.clone()),
+            ("r", self.fps.to_string()),
+            ("g", encoding.gop_size.unwrap_or(self.fps * 2).to_string()),
+            ("b:v", encoding.video_bitrate.clone()),
+            ("threads", encoding.threads.to_string()),
+        ];
+        if let Some((width, height)) = encoding.output_resolution {
+            args.push(("vf", format!("scale={width}:{height}")));
+        }
+        if encoding.audio {
+            args.extend([
+                ("acodec", "aac".to_string()),
+                ("ar", "44100".to_string()),
+                ("b:a", "128k".to_string()),
+            ]);
+        }
+
+        args.into_iter()
+            .map(|(arg, value)| (arg.to_string(), value))
+            .collect()
+    }
+}
+
+/// Recording of the stream into files, which are split into segments of a fixed duration.
+#[derive(Clone, Debug)]
+pub struct Recording {
+    pub directory: PathBuf,
+    /// strftime pattern of the file names, filled with the time the segment started
+    pub file_pattern: String,
+    pub segment_duration_s: u64,
+    /// The oldest segments get deleted once all segments together are bigger than this
+    pub max_disk_usage_bytes: Option<u64>,
+}
+
+impl Recording {
+    fn output(&self) -> FfmpegOutput {
+        let mut options = vec![
+            ("segment_time", self.segment_duration_s.to_string()),
+            ("reset_timestamps", "1".to_string()),
+            ("strftime", "1".to_string()),
+        ];
+        // A regular mp4 is only playable once ffmpeg wrote the index at the very end, so a crash would leave an
+        // unplayable file. Fragmented mp4 files can be played up to the last complete fragment.
+        let extension = Path::new(&self.file_pattern)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        if matches!(extension.as_deref(), Some("mp4" | "m4v" | "mov")) {
+            options.push((
+                "segment_format_options",
+                "movflags=+frag_keyframe+empty_moov+default_base_moof".to_string(),
+            ));
+        }
+
+        FfmpegOutput {
+            format: Some("segment"),
+            options: options
+                .into_iter()
+                .map(|(option, value)| (option.to_string(), value))
+                .collect(),
+            target: self
+                .directory
+                .join(&self.file_pattern)
+                .display()
+                .to_string(),
+            ignore_failure: false,
+        }
+    }
+
+    /// Returns all segments in the directory, the oldest one first.
+    /// Segments are all files whose name matches the whole file pattern, so that we never delete unrelated files.
+    fn list_segments(&self) -> std::io::Result<Vec<Segment>> {
+        let mut segments = std::fs::read_dir(&self.directory)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| self.is_segment_name(&entry.file_name().to_string_lossy()))
+            .filter_map(|entry| {
+                let metadata = entry.metadata().ok()?;
+                metadata.is_file().then_some(Segment {
+                    path: entry.path(),
+                    size: metadata.len(),
+                    modified: metadata.modified().ok()?,
+                })
+            })
+            .collect::<Vec<_>>();
+        segments.sort_by_key(|segment| segment.modified);
+
+        Ok(segments)
+    }
+
+    fn is_segment_name(&self, file_name: &str) -> bool {
+        chrono::format::parse(
+            &mut Parsed::new(),
+            file_name,
+            StrftimeItems::new(&self.file_pattern),
+        )
+        .is_ok()
+    }
+}
+
+struct Segment {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// Deletes the oldest segments until the remaining ones fit into `max_disk_usage_bytes`.
+/// The newest segment is never deleted, as ffmpeg is currently writing it.
+fn remove_oldest_segments(segments: &[Segment], max_disk_usage_bytes: u64) {
+    let mut disk_usage_bytes: u64 = segments.iter().map(|segment| segment.size).sum();
+    for segment in segments.iter().take(segments.len().saturating_sub(1)) {
+        if disk_usage_bytes <= max_disk_usage_bytes {
+            break;
+        }
+
+        match std::fs::remove_file(&segment.path) {
+            Ok(()) => {
+                info!(
+                    "Removed recording {} to limit the disk usage",
+                    segment.path.display()
+                );
+                disk_usage_bytes -= segment.size;
+            }
+            Err(err) => warn!(
+                "Failed to remove old recording {}: {err}",
+                segment.path.display()
+            ),
+        }
+    }
+}
+
+/// Streaming formats that can be played in a browser, served by a plain web server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WebStreamFormat {
+    /// HTTP Live Streaming, writes a `stream.m3u8` playlist
+    Hls,
+    /// MPEG-DASH, writes a `stream.mpd` manifest
+    Dash,
+}
+
+/// Segments and playlist of a [`WebStreamFormat`], which are written into a directory.
+#[derive(Clone, Debug)]
+pub struct WebStream {
+    pub directory: PathBuf,
+    pub format: WebStreamFormat,
+    pub segment_duration_s: u32,
+    /// Number of segments in the playlist. Older segments get deleted.
+    pub playlist_size: u32,
+}
+
+impl WebStream {
+    fn output(&self) -> FfmpegOutput {
+        let (format, playlist, options) = match self.format {
+            WebStreamFormat::Hls => (
+                "hls",
+                "stream.m3u8",
+                vec![
+                    ("hls_time", self.segment_duration_s.to_string()),
+                    ("hls_list_size", self.playlist_size.to_string()),
+                    // Otherwise ffmpeg keeps all segments around
+                    ("hls_flags", "delete_segments".to_string()),
+                ],
+            ),
+            // Segments that left the window get deleted by default
+            WebStreamFormat::Dash => (
+                "dash",
+                "stream.mpd",
+                vec![
+                    ("seg_duration", self.segment_duration_s.to_string()),
+                    ("window_size", self.playlist_size.to_string()),
+                ],
+            ),
+        };
+
+        FfmpegOutput {
+            format: Some(format),
+            options: options
+                .into_iter()
+                .map(|(option, value)| (option.to_string(), value))
+                .collect(),
+            target: self.directory.join(playlist).display().to_string(),
+            ignore_failure: false,
+        }
+    }
+}
+
+/// A single output of ffmpeg, e.g. a file or an rtmp server.
+struct FfmpegOutput {
+    /// Format of the output, guessed by ffmpeg based on the target if [`None`]
+    format: Option<&'static str>,
+    /// Options of the muxer
+    options: Vec<(String, String)>,
+    target: String,
+    /// Keep writing the other outputs if this one fails. Only applies if there are multiple outputs.
+    ignore_failure: bool,
+}
+
+impl FfmpegOutput {
+    /// Formats the output for the tee muxer, e.g. `[f=flv:onfail=ignore]rtmp://127.0.0.1/live`
+    fn tee_slave(&self) -> String {
+        let mut options: Vec<String> = self
+            .format
+            .map(|format| format!("f={format}"))
+            .into_iter()
+            .chain(
+                self.options
+                    .iter()
+                    .map(|(option, value)| format!("{option}={value}")),
+            )
+            .collect();
+        if self.ignore_failure {
+            options.push("onfail=ignore".to_string());
+        }
+
+        if options.is_empty() {
+            self.target.clone()
+        } else {
+            format!("[{}]{}", options.join(":"), self.target)
+        }
+    }
+}
+
+/// Forwards the output of ffmpeg into our log.
+async fn log_ffmpeg_output(stderr: ChildStderr) {
+    let mut lines = BufReader::new(stderr).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match ffmpeg_log_level(&line) {
+            Level::Error => error!("ffmpeg: {line}"),
+            Level::Warn => warn!("ffmpeg: {line}"),
+            Level::Info => info!("ffmpeg: {line}"),
+            Level::Debug | Level::Trace => debug!("ffmpeg: {line}"),
+        }
+    }
+}
+
+/// Determines the level of a line ffmpeg logged with `-loglevel level`.
+/// The level follows the (optional) component, e.g. `[libx264 @ 0x55d0c8a0] [warning] some message`.
+fn ffmpeg_log_level(line: &str) -> Level {
+    const LEVELS: [(&str, Level); 8] = [
+        ("[panic] ", Level::Error),
+        ("[fatal] ", Level::Error),
+        ("[error] ", Level::Error),
+        ("[warning] ", Level::Warn),
+        ("[info] ", Level::Info),
+        ("[verbose] ", Level::Debug),
+        ("[debug] ", Level::Debug),
+        ("[trace] ", Level::Trace),
+    ];
+
+    LEVELS
+        .iter()
+        .filter_map(|(tag, level)| line.find(tag).map(|position| (position, *level)))
+        .min_by_key(|(position, _)| *position)
+        .map(|(_, level)| level)
+        .unwrap_or(Level::Info)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("[error] Connection refused", Level::Error)]
+    #[case("[fatal] Out of memory", Level::Error)]
+    #[case("[libx264 @ 0x55d0c8a0] [warning] some message", Level::Warn)]
+    #[case(
+        "[flv @ 0x55d0c8a0] [info] some message containing [error] ",
+        Level::Info
+    )]
+    #[case("[verbose] Opening file", Level::Debug)]
+    #[case("Some line without level", Level::Info)]
+    fn test_ffmpeg_log_level(#[case] line: &str, #[case] expected: Level) {
+        assert_eq!(ffmpeg_log_level(line), expected);
+    }
+
+    #[test]
+    fn test_remove_oldest_segments() {
+        let directory =
+            std::env::temp_dir().join(format!("breakwater_test_{}_recordings", std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let recording = Recording {
+            directory: directory.clone(),
+            file_pattern: "%Y-%m-%d_%H-%M-%S.mkv".to_string(),
+            segment_duration_s: 3600,
+            max_disk_usage_bytes: Some(250),
+        };
+        let now = SystemTime::now();
+        // Names are intentionally not in chronological order, only the modification time counts
+        for (name, age_s) in [
+            ("2000-01-01_00-00-03.mkv", 300),
+            ("2000-01-01_00-00-02.mkv", 200),
+            ("2000-01-01_00-00-04.mkv", 100),
+            ("2000-01-01_00-00-01.mkv", 0),
+        ] {
+            let file = std::fs::File::create(directory.join(name)).unwrap();
+            file.set_len(100).unwrap();
+            file.set_modified(now - Duration::from_secs(age_s)).unwrap();
+        }
+        // Not recordings, even though they have the same extension or start the same way
+        std::fs::write(directory.join("holidays.mkv"), [0; 1000]).unwrap();
+        std::fs::write(directory.join("2000-01-01_notes.txt"), [0; 1000]).unwrap();
+
+        let segments = recording.list_segments().unwrap();
+        assert_eq!(segments.len(), 4);
+        remove_oldest_segments(&segments, recording.max_disk_usage_bytes.unwrap());
+
+        let mut remaining = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        remaining.sort();
+        assert_eq!(
+            remaining,
+            vec![
+                "2000-01-01_00-00-01.mkv",
+                "2000-01-01_00-00-04.mkv",
+                "2000-01-01_notes.txt",
+                "holidays.mkv"
+            ]
+        );
+
+        // The newest segment is kept even if it's too big on its own
+        let segments = recording.list_segments().unwrap();
+        remove_oldest_segments(&segments, 0);
+        assert!(!directory.join("2000-01-01_00-00-04.mkv").exists());
+        assert!(directory.join("2000-01-01_00-00-01.mkv").exists());
+
+        std::fs::remove_dir_all(&directory).unwrap();
     }
 }
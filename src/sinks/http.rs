@@ -0,0 +1,453 @@
+use std::{sync::Arc, time::Duration};
+
+use image::{
+    codecs::{jpeg::JpegEncoder, png::PngEncoder},
+    ColorType, ImageEncoder,
+};
+use log::{debug, info};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+    task, time,
+};
+
+use crate::{
+    args::Args,
+    framebuffer::FrameBuffer,
+    sinks::{Sink, SinkContext, SinkFuture},
+};
+
+/// Requests with a bigger header are rejected. We only serve a few GET requests, so this is plenty.
+const MAX_REQUEST_HEADER_SIZE: usize = 8 * 1024;
+/// Browsers send the request header right away, so clients that take longer only hold up a task
+const REQUEST_HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+const MJPEG_BOUNDARY: &str = "breakwater_frame";
+
+const INDEX_HTML: &str = r#"<!DOCTYPE html>
+<html>
+<head><title>breakwater</title></head>
+<body style="margin: 0; background: black">
+<img src="/stream.mjpeg" style="width: 100vw; height: 100vh; object-fit: contain">
+</body>
+</html>
+"#;
+
+/// Serves a live view of the canvas over HTTP, which can be watched in any browser.
+///
+/// * `/` - HTML page showing the live view
+/// * `/stream.mjpeg` - MJPEG stream (`multipart/x-mixed-replace`)
+/// * `/snapshot.png` - PNG of the current canvas
+#[derive(Clone)]
+pub struct HttpSink {
+    fb: Arc<FrameBuffer>,
+    listen_address: String,
+    fps: u32,
+    jpeg_quality: u8,
+}
+
+impl Sink for HttpSink {
+    fn name(&self) -> &str {
+        "HTTP"
+    }
+
+    fn run(&self) -> SinkFuture<'_> {
+        Box::pin(async {
+            let listener = TcpListener::bind(&self.listen_address).await?;
+            info!("Serving live view on http://{}", listener.local_addr()?);
+            self.serve(listener).await
+        })
+    }
+}
+
+impl HttpSink {
+    pub fn new(args: &Args, context: &SinkContext) -> Option<Self> {
+        args.http_listen_address
+            .as_ref()
+            .map(|listen_address| HttpSink {
+                fb: Arc::clone(&context.fb),
+                listen_address: listen_address.clone(),
+                fps: args.http_fps,
+                jpeg_quality: args.http_jpeg_quality,
+            })
+    }
+
+    pub async fn serve(&self, listener: TcpListener) -> std::io::Result<()> {
+        // All clients share the same JPEGs, so that every frame is only encoded once
+        let (frame_tx, _) = watch::channel(Arc::new(Vec::new()));
+        let encoder = self.encode_frames(frame_tx.clone());
+        let accept = async {
+            loop {
+                let (stream, _) = listener.accept().await?;
+                let sink = self.clone();
+                let frame_tx = frame_tx.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = sink.handle_client(stream, frame_tx).await {
+                        // Mostly clients closing the browser tab
+                        debug!("HTTP client failed: {err}");
+                    }
+                });
+            }
+        };
+
+        tokio::select! {
+            result = encoder => result,
+            result = accept => result,
+        }
+    }
+
+    /// Encodes the canvas into a JPEG in the configured fps, as long as somebody is watching the stream.
+    async fn encode_frames(&self, frame_tx: watch::Sender<Arc<Vec<u8>>>) -> std::io::Result<()> {
+        let mut interval = time::interval(Duration::from_micros(1_000_000 / self.fps as u64));
+        loop {
+            interval.tick().await;
+            if frame_tx.receiver_count() == 0 {
+                continue;
+            }
+
+            let fb = Arc::clone(&self.fb);
+            let jpeg_quality = self.jpeg_quality;
+            // Encoding takes a while, so we don't want to block the runtime
+            let jpeg = task::spawn_blocking(move || encode_jpeg(&fb, jpeg_quality))
+                .await
+                .expect("Failed to join JPEG encoding thread")?;
+            frame_tx.send_replace(Arc::new(jpeg));
+        }
+    }
+
+    async fn handle_client(
+        &self,
+        mut stream: TcpStream,
+        frame_tx: watch::Sender<Arc<Vec<u8>>>,
+    ) -> std::io::Result<()> {
+        let request = time::timeout(REQUEST_HEADER_TIMEOUT, read_request(&mut stream))
+            .await
+            .map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "Timed out reading the request header",
+                )
+            })??;
+        let Some((method, path)) = request else {
+            return write_response(&mut stream, "400 Bad Request", "text/plain", b"Bad request")
+                .await;
+        };
+        if method != "GET" {
+            return write_response(
+                &mut stream,
+                "405 Method Not Allowed",
+                "text/plain",
+                b"Method not allowed",
+            )
+            .await;
+        }
+
+        match path.as_str() {
+            "/" | "/index.html" => {
+                write_response(&mut stream, "200 OK", "text/html", INDEX_HTML.as_bytes()).await
+            }
+            "/stream.mjpeg" => stream_mjpeg(&mut stream, frame_tx).await,
+            "/snapshot.png" => {
+                let fb = Arc::clone(&self.fb);
+                let png = task::spawn_blocking(move || encode_png(&fb))
+                    .await
+                    .expect("Failed to join PNG encoding thread")?;
+                write_response(&mut stream, "200 OK", "image/png", &png).await
+            }
+            _ => write_response(&mut stream, "404 Not Found", "text/plain", b"Not found").await,
+        }
+    }
+}
+
+/// Reads the request header and returns the method and path (without query string).
+/// Returns [`None`] if the request is malformed.
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<(String, String)>> {
+    let mut header = Vec::new();
+    let mut buffer = [0; 1024];
+    while !header.windows(4).any(|window| window == b"\r\n\r\n") {
+        if header.len() > MAX_REQUEST_HEADER_SIZE {
+            return Ok(None);
+        }
+        let read = stream.read(&mut buffer).await?;
+        if read == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        header.extend_from_slice(&buffer[..read]);
+    }
+
+    let header = String::from_utf8_lossy(&header);
+    let mut request_line = header.lines().next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+    let path = target.split('?').next().unwrap_or_default();
+
+    Ok(Some((method.to_string(), path.to_string())))
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .await?;
+    stream.write_all(body).await?;
+    stream.flush().await
+}
+
+/// Sends every new frame as a part of a `multipart/x-mixed-replace` response, until the client disconnects.
+///
+/// Only stream viewers subscribe to the frames, as the encoder only runs as long as there are subscribers.
+async fn stream_mjpeg(
+    stream: &mut TcpStream,
+    frame_tx: watch::Sender<Arc<Vec<u8>>>,
+) -> std::io::Result<()> {
+    let mut frame_rx = frame_tx.subscribe();
+    // Otherwise we would keep the channel open and never notice the encoder stopping
+    drop(frame_tx);
+
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={MJPEG_BOUNDARY}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+            )
+            .as_bytes(),
+        )
+        .await?;
+
+    loop {
+        frame_rx
+            .changed()
+            .await
+            .map_err(|_| std::io::Error::other("JPEG encoder stopped"))?;
+        let jpeg = Arc::clone(&frame_rx.borrow_and_update());
+        stream
+            .write_all(
+                format!(
+                    "--{MJPEG_BOUNDARY}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                    jpeg.len()
+                )
+                .as_bytes(),
+            )
+            .await?;
+        stream.write_all(&jpeg).await?;
+        stream.write_all(b"\r\n").await?;
+        stream.flush().await?;
+    }
+}
+
+fn rgb_pixels(fb: &FrameBuffer) -> Vec<u8> {
+    fb.snapshot()
+        .iter()
+        .flat_map(|pixel| {
+            let [r, g, b, _] = pixel.to_le_bytes();
+            [r, g, b]
+        })
+        .collect()
+}
+
+fn encode_jpeg(fb: &FrameBuffer, quality: u8) -> std::io::Result<Vec<u8>> {
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(&mut jpeg, quality)
+        .write_image(
+            &rgb_pixels(fb),
+            fb.get_width() as u32,
+            fb.get_height() as u32,
+            ColorType::Rgb8,
+        )
+        .map_err(std::io::Error::other)?;
+
+    Ok(jpeg)
+}
+
+fn encode_png(fb: &FrameBuffer) -> std::io::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .write_image(
+            &rgb_pixels(fb),
+            fb.get_width() as u32,
+            fb.get_height() as u32,
+            ColorType::Rgb8,
+        )
+        .map_err(std::io::Error::other)?;
+
+    Ok(png)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::GenericImageView;
+    use rstest::rstest;
+
+    /// Starts the sink on a random port and returns the address to connect to.
+    async fn start_sink(fb: Arc<FrameBuffer>) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let sink = HttpSink {
+            fb,
+            listen_address: address.to_string(),
+            fps: 30,
+            jpeg_quality: 80,
+        };
+        tokio::spawn(async move { sink.serve(listener).await });
+
+        address
+    }
+
+    /// Sends a GET request and returns the response header and the start of the body.
+    async fn get(address: std::net::SocketAddr, path: &str) -> (String, Vec<u8>) {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes())
+            .await
+            .unwrap();
+
+        // The MJPEG stream never ends, so we stop once the second frame starts
+        let mut response = Vec::new();
+        let mut buffer = [0; 4096];
+        let part_start = format!("--{MJPEG_BOUNDARY}\r\n");
+        time::timeout(Duration::from_secs(10), async {
+            loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                response.extend_from_slice(&buffer[..read]);
+                let parts = response
+                    .windows(part_start.len())
+                    .filter(|window| *window == part_start.as_bytes())
+                    .count();
+                if read == 0 || parts >= 2 {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("Timed out waiting for response");
+
+        let header_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap();
+        (
+            String::from_utf8_lossy(&response[..header_end]).to_string(),
+            response[header_end + 4..].to_vec(),
+        )
+    }
+
+    #[rstest]
+    #[case("/does-not-exist", "HTTP/1.1 404 Not Found")]
+    #[case("/", "HTTP/1.1 200 OK")]
+    #[case("/index.html?foo=bar", "HTTP/1.1 200 OK")]
+    #[tokio::test]
+    async fn test_routes(#[case] path: &str, #[case] expected_status: &str) {
+        let address = start_sink(Arc::new(FrameBuffer::new(4, 3))).await;
+        let (header, _) = get(address, path).await;
+        assert!(header.starts_with(expected_status), "{header}");
+    }
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let fb = Arc::new(FrameBuffer::new(4, 3));
+        fb.set(1, 2, u32::from_le_bytes([0x12, 0x34, 0x56, 0]));
+        let address = start_sink(Arc::clone(&fb)).await;
+
+        let (header, body) = get(address, "/snapshot.png").await;
+        assert!(header.starts_with("HTTP/1.1 200 OK"), "{header}");
+        assert!(header.contains("Content-Type: image/png"));
+
+        let image = image::load_from_memory(&body).unwrap();
+        assert_eq!(image.dimensions(), (4, 3));
+        assert_eq!(image.get_pixel(1, 2).0, [0x12, 0x34, 0x56, 0xff]);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0, 0xff]);
+    }
+
+    #[tokio::test]
+    async fn test_only_stream_viewers_subscribe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let sink = HttpSink {
+            fb: Arc::new(FrameBuffer::new(4, 3)),
+            listen_address: address.to_string(),
+            fps: 30,
+            jpeg_quality: 80,
+        };
+        let (frame_tx, _) = watch::channel(Arc::new(Vec::new()));
+
+        let mut client = TcpStream::connect(address).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        let client_frame_tx = frame_tx.clone();
+        tokio::spawn(async move { sink.handle_client(stream, client_frame_tx).await });
+
+        // Clients still sending the request header don't watch the stream yet
+        client
+            .write_all(b"GET /stream.mjpeg HTTP/1.1\r\n")
+            .await
+            .unwrap();
+        time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(frame_tx.receiver_count(), 0);
+
+        client.write_all(b"\r\n").await.unwrap();
+        time::timeout(Duration::from_secs(5), async {
+            while frame_tx.receiver_count() == 0 {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Stream viewer did not subscribe");
+    }
+
+    #[tokio::test]
+    async fn test_request_header_timeout() {
+        let address = start_sink(Arc::new(FrameBuffer::new(4, 3))).await;
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+
+        // The connection gets closed without a response
+        let mut response = Vec::new();
+        time::timeout(
+            REQUEST_HEADER_TIMEOUT + Duration::from_secs(5),
+            stream.read_to_end(&mut response),
+        )
+        .await
+        .expect("Connection was not closed")
+        .unwrap();
+        assert!(response.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_mjpeg_stream() {
+        let address = start_sink(Arc::new(FrameBuffer::new(40, 30))).await;
+
+        let (header, body) = get(address, "/stream.mjpeg").await;
+        assert!(header.starts_with("HTTP/1.1 200 OK"), "{header}");
+        assert!(header.contains(&format!(
+            "Content-Type: multipart/x-mixed-replace; boundary={MJPEG_BOUNDARY}"
+        )));
+
+        let part_header_end = body
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap();
+        let part_header = String::from_utf8_lossy(&body[..part_header_end]);
+        assert!(part_header.starts_with(&format!("--{MJPEG_BOUNDARY}\r\n")));
+        assert!(part_header.contains("Content-Type: image/jpeg"));
+
+        let content_length: usize = part_header
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+        let jpeg_start = part_header_end + 4;
+        let jpeg = image::load_from_memory(&body[jpeg_start..jpeg_start + content_length]).unwrap();
+        assert_eq!(jpeg.dimensions(), (40, 30));
+    }
+}
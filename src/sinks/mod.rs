@@ -1,3 +1,259 @@
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{broadcast, mpsc},
+    task::JoinHandle,
+};
+
+use crate::{
+    args::Args,
+    framebuffer::FrameBuffer,
+    statistics::{StatisticsEvent, StatisticsInformationEvent},
+};
+
 pub mod ffmpeg;
+pub mod http;
+pub mod snapshot;
 #[cfg(feature = "vnc")]
 pub mod vnc;
+
+/// The future returned by [`Sink::run`] and [`Sink::shutdown`].
+/// It needs to be boxed, so that different sinks can be stored next to each other in the [`SinkRegistry`].
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = std::io::Result<()>> + Send + 'a>>;
+
+/// The state of a sink, exposed as Prometheus metric.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum SinkState {
+    Running,
+    /// Some part of the sink (e.g. an external process) failed and is waiting to be restarted
+    Restarting,
+    Stopped,
+    Failed,
+}
+
+impl SinkState {
+    pub const ALL: [SinkState; 4] = [
+        SinkState::Running,
+        SinkState::Restarting,
+        SinkState::Stopped,
+        SinkState::Failed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SinkState::Running => "running",
+            SinkState::Restarting => "restarting",
+            SinkState::Stopped => "stopped",
+            SinkState::Failed => "failed",
+        }
+    }
+}
+
+/// Everything breakwater offers to sinks when constructing them.
+#[derive(Clone)]
+pub struct SinkContext {
+    pub fb: Arc<FrameBuffer>,
+    pub statistics_tx: mpsc::Sender<StatisticsEvent>,
+    /// Sinks interested in the statistics should call [`broadcast::Sender::subscribe`] on this
+    pub statistics_information_tx: broadcast::Sender<StatisticsInformationEvent>,
+}
+
+impl SinkContext {
+    /// Reports the current state of the sink with the given name to the statistics.
+    pub async fn report_sink_state(&self, sink: &str, state: SinkState) {
+        // Sinks should keep working, even if nobody is interested in the statistics
+        let _ = self
+            .statistics_tx
+            .send(StatisticsEvent::SinkStateChanged {
+                sink: sink.to_string(),
+                state,
+            })
+            .await;
+    }
+}
+
+/// Something that consumes the framebuffer, e.g. to display or stream it.
+pub trait Sink: Send + Sync {
+    /// Name of the sink, used for logging.
+    fn name(&self) -> &str;
+
+    /// Runs the sink. The returned future normally only completes if the sink failed.
+    ///
+    /// Sinks that need a dedicated OS thread (e.g. because of blocking FFI calls) should spawn it here and complete
+    /// the future once the thread has finished.
+    fn run(&self) -> SinkFuture<'_>;
+
+    /// Called once breakwater shuts down, e.g. to flush some files. [`Sink::run`] might still be running at that point.
+    fn shutdown(&self) -> SinkFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Creates a sink based on the configuration. Returns [`None`] if the sink is not enabled.
+pub type SinkFactory =
+    Box<dyn Fn(&Args, &SinkContext) -> std::io::Result<Option<Arc<dyn Sink>>> + Send + Sync>;
+
+/// Collection of all the sinks breakwater knows about.
+/// Users of the breakwater library can [`register`](SinkRegistry::register) their own sinks next to the builtin ones.
+#[derive(Default)]
+pub struct SinkRegistry {
+    factories: Vec<SinkFactory>,
+}
+
+impl SinkRegistry {
+    /// Creates a registry containing all sinks that ship with breakwater.
+    pub fn with_builtin_sinks() -> Self {
+        let mut registry = SinkRegistry::default();
+        registry.register(|args, context| Ok(ffmpeg::FfmpegSink::new(args, context)));
+        registry.register(|args, context| Ok(snapshot::SnapshotSink::new(args, context)));
+        registry.register(|args, context| Ok(http::HttpSink::new(args, context)));
+        #[cfg(feature = "vnc")]
+        registry.register(|args, context| Ok(Some(vnc::VncSink::new(args, context))));
+
+        registry
+    }
+
+    pub fn register<S: Sink + 'static>(
+        &mut self,
+        factory: impl Fn(&Args, &SinkContext) -> std::io::Result<Option<S>> + Send + Sync + 'static,
+    ) {
+        self.factories.push(Box::new(move |args, context| {
+            Ok(factory(args, context)?.map(|sink| Arc::new(sink) as Arc<dyn Sink>))
+        }));
+    }
+
+    /// Creates all enabled sinks and spawns a task for each of them.
+    pub fn start(&self, args: &Args, context: &SinkContext) -> std::io::Result<RunningSinks> {
+        let mut sinks = Vec::new();
+        for factory in &self.factories {
+            if let Some(sink) = factory(args, context)? {
+                sinks.push(sink);
+            }
+        }
+
+        let threads = sinks
+            .iter()
+            .map(|sink| {
+                info!("Starting {} sink", sink.name());
+                let sink = Arc::clone(sink);
+                let context = context.clone();
+                tokio::spawn(async move {
+                    context
+                        .report_sink_state(sink.name(), SinkState::Running)
+                        .await;
+                    let result = sink.run().await;
+                    let state = match &result {
+                        Ok(()) => SinkState::Stopped,
+                        Err(err) => {
+                            error!("{} sink failed: {err}", sink.name());
+                            SinkState::Failed
+                        }
+                    };
+                    context.report_sink_state(sink.name(), state).await;
+                    result
+                })
+            })
+            .collect();
+
+        Ok(RunningSinks { sinks, threads })
+    }
+}
+
+pub struct RunningSinks {
+    sinks: Vec<Arc<dyn Sink>>,
+    threads: Vec<JoinHandle<std::io::Result<()>>>,
+}
+
+impl RunningSinks {
+    /// Waits for all sinks to stop and returns the first error.
+    pub async fn join(&mut self) -> std::io::Result<()> {
+        for thread in self.threads.drain(..) {
+            thread.await.map_err(std::io::Error::other)??;
+        }
+
+        Ok(())
+    }
+
+    /// Calls [`Sink::shutdown`] on all sinks.
+    pub async fn shutdown(&self) -> std::io::Result<()> {
+        for sink in &self.sinks {
+            sink.shutdown().await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+    use std::sync::Mutex;
+
+    /// Records which methods got called, so that we can check the lifecycle
+    struct RecordingSink {
+        calls: Arc<Mutex<Vec<&'static str>>>,
+    }
+
+    impl Sink for RecordingSink {
+        fn name(&self) -> &str {
+            "recording"
+        }
+
+        fn run(&self) -> SinkFuture<'_> {
+            Box::pin(async {
+                self.calls.lock().unwrap().push("run");
+                Ok(())
+            })
+        }
+
+        fn shutdown(&self) -> SinkFuture<'_> {
+            Box::pin(async {
+                self.calls.lock().unwrap().push("shutdown");
+                Ok(())
+            })
+        }
+    }
+
+    fn context() -> SinkContext {
+        SinkContext {
+            fb: Arc::new(FrameBuffer::new(4, 3)),
+            statistics_tx: mpsc::channel(1).0,
+            statistics_information_tx: broadcast::channel(1).0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_sink_lifecycle() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let mut registry = SinkRegistry::default();
+        let calls_for_factory = Arc::clone(&calls);
+        registry.register(move |_, _| {
+            Ok(Some(RecordingSink {
+                calls: Arc::clone(&calls_for_factory),
+            }))
+        });
+        // Disabled sinks are not started
+        registry.register(|_, _| Ok(None::<RecordingSink>));
+
+        let args = Args::parse_from(["breakwater"]);
+        let mut sinks = registry.start(&args, &context()).unwrap();
+        sinks.join().await.unwrap();
+        sinks.shutdown().await.unwrap();
+
+        assert_eq!(*calls.lock().unwrap(), vec!["run", "shutdown"]);
+    }
+
+    #[tokio::test]
+    async fn test_failing_factory() {
+        let mut registry = SinkRegistry::default();
+        registry.register(|_, _| -> std::io::Result<Option<RecordingSink>> {
+            Err(std::io::Error::other("Invalid configuration"))
+        });
+
+        let args = Args::parse_from(["breakwater"]);
+        assert!(registry.start(&args, &context()).is_err());
+    }
+}
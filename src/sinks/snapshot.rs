@@ -0,0 +1,426 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::Local;
+use clap::ValueEnum;
+use image::{
+    codecs::gif::{GifEncoder, Repeat},
+    Delay, Frame, RgbImage,
+};
+use log::{info, warn};
+use tokio::{task, time};
+
+use crate::{
+    args::Args,
+    framebuffer::FrameBuffer,
+    sinks::{Sink, SinkContext, SinkFuture},
+};
+
+const SNAPSHOT_FILE_PREFIX: &str = "breakwater_snapshot_";
+const SNAPSHOT_FILE_SUFFIX: &str = ".png";
+/// Sorts the same way lexicographically and chronologically, so we can find the oldest snapshots by their file names
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d_%H-%M-%S";
+
+/// The file format of the timelapse assembled from the snapshots.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimelapseFormat {
+    /// Animated GIF, which is small and plays everywhere, but is limited to 256 colors per frame
+    Gif,
+    /// Animated PNG, which is lossless, but much larger
+    Apng,
+}
+
+/// Periodically writes a PNG of the canvas into a directory.
+pub struct SnapshotSink {
+    fb: Arc<FrameBuffer>,
+    directory: PathBuf,
+    interval: Duration,
+    /// Maximum number of snapshots kept in the directory, the oldest ones get deleted first
+    max_snapshots: Option<usize>,
+    timelapse_format: Option<TimelapseFormat>,
+    timelapse_fps: u32,
+}
+
+impl Sink for SnapshotSink {
+    fn name(&self) -> &str {
+        "snapshot"
+    }
+
+    fn run(&self) -> SinkFuture<'_> {
+        Box::pin(self.take_snapshots())
+    }
+
+    fn shutdown(&self) -> SinkFuture<'_> {
+        Box::pin(self.assemble_timelapse())
+    }
+}
+
+impl SnapshotSink {
+    pub fn new(args: &Args, context: &SinkContext) -> Option<Self> {
+        args.snapshot_directory
+            .as_ref()
+            .map(|directory| SnapshotSink {
+                fb: Arc::clone(&context.fb),
+                directory: PathBuf::from(directory),
+                interval: Duration::from_secs(args.snapshot_interval_s),
+                max_snapshots: args.snapshot_max_files,
+                timelapse_format: args.snapshot_timelapse,
+                timelapse_fps: args.snapshot_timelapse_fps,
+            })
+    }
+
+    async fn take_snapshots(&self) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+
+        let mut interval = time::interval(self.interval);
+        loop {
+            interval.tick().await;
+            // E.g. a full disk might recover, so we keep trying with the next interval
+            if let Err(err) = self.take_snapshot().await {
+                warn!(
+                    "Failed to take snapshot in {}: {err}",
+                    self.directory.display()
+                );
+            }
+        }
+    }
+
+    /// Assembles the timelapse, if configured.
+    async fn assemble_timelapse(&self) -> std::io::Result<()> {
+        let Some(timelapse_format) = self.timelapse_format else {
+            return Ok(());
+        };
+
+        let directory = self.directory.clone();
+        let snapshots = task::spawn_blocking(move || list_snapshots(&directory))
+            .await
+            .expect("Failed to join snapshot listing thread")?;
+        if snapshots.is_empty() {
+            return Ok(());
+        }
+
+        let extension = match timelapse_format {
+            TimelapseFormat::Gif => "gif",
+            TimelapseFormat::Apng => "png",
+        };
+        let timelapse_file = self.directory.join(format!(
+            "breakwater_timelapse_{}.{extension}",
+            Local::now().format(TIMESTAMP_FORMAT)
+        ));
+        info!(
+            "Assembling timelapse {} out of {} snapshots",
+            timelapse_file.display(),
+            snapshots.len()
+        );
+
+        let fps = self.timelapse_fps;
+        task::spawn_blocking(move || {
+            write_timelapse(&snapshots, &timelapse_file, timelapse_format, fps)
+        })
+        .await
+        .expect("Failed to join timelapse thread")
+    }
+
+    async fn take_snapshot(&self) -> std::io::Result<()> {
+        let file = self.directory.join(format!(
+            "{SNAPSHOT_FILE_PREFIX}{}{SNAPSHOT_FILE_SUFFIX}",
+            Local::now().format(TIMESTAMP_FORMAT)
+        ));
+        let width = self.fb.get_width() as u32;
+        let height = self.fb.get_height() as u32;
+        let pixels = self.fb.snapshot();
+
+        // Encoding a PNG takes a while, so we don't want to block the runtime
+        task::spawn_blocking(move || {
+            let rgb = pixels
+                .iter()
+                .flat_map(|pixel| {
+                    let [r, g, b, _] = pixel.to_le_bytes();
+                    [r, g, b]
+                })
+                .collect();
+            RgbImage::from_raw(width, height, rgb)
+                .expect("Framebuffer snapshot has the wrong size")
+                .save(&file)
+                .map_err(std::io::Error::other)
+        })
+        .await
+        .expect("Failed to join snapshot thread")?;
+
+        if let Some(max_snapshots) = self.max_snapshots {
+            // Listing and deleting files blocks, which must not happen on the async runtime
+            let directory = self.directory.clone();
+            task::spawn_blocking(move || remove_oldest_snapshots(&directory, max_snapshots))
+                .await
+                .expect("Failed to join snapshot cleanup thread")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes the oldest snapshots, so that at most `max_snapshots` are left.
+fn remove_oldest_snapshots(directory: &Path, max_snapshots: usize) -> std::io::Result<()> {
+    let snapshots = list_snapshots(directory)?;
+    for snapshot in snapshots
+        .iter()
+        .take(snapshots.len().saturating_sub(max_snapshots))
+    {
+        if let Err(err) = std::fs::remove_file(snapshot) {
+            warn!(
+                "Failed to remove old snapshot {}: {err}",
+                snapshot.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns all snapshots in the directory, the oldest one first.
+fn list_snapshots(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut snapshots = std::fs::read_dir(directory)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let file_name = entry.file_name();
+            let file_name = file_name.to_string_lossy();
+            file_name.starts_with(SNAPSHOT_FILE_PREFIX) && file_name.ends_with(SNAPSHOT_FILE_SUFFIX)
+        })
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    snapshots.sort();
+
+    Ok(snapshots)
+}
+
+/// Reads the snapshots one by one and appends them to the timelapse, so that we never need to keep all of them in memory.
+fn write_timelapse(
+    snapshots: &[PathBuf],
+    timelapse_file: &Path,
+    format: TimelapseFormat,
+    fps: u32,
+) -> std::io::Result<()> {
+    let writer = BufWriter::new(File::create(timelapse_file)?);
+
+    match format {
+        TimelapseFormat::Gif => {
+            // Speed 10 is the default of the gif crate, the slowest speed 1 takes ages for big canvases
+            let mut encoder = GifEncoder::new_with_speed(writer, 10);
+            encoder
+                .set_repeat(Repeat::Infinite)
+                .map_err(std::io::Error::other)?;
+            for snapshot in snapshots {
+                let frame = image::open(snapshot)
+                    .map_err(std::io::Error::other)?
+                    .into_rgba8();
+                encoder
+                    .encode_frame(Frame::from_parts(
+                        frame,
+                        0,
+                        0,
+                        Delay::from_numer_denom_ms(1000, fps),
+                    ))
+                    .map_err(std::io::Error::other)?;
+            }
+        }
+        TimelapseFormat::Apng => {
+            // All frames need the same size, so we take it from the first one
+            let first = image::open(&snapshots[0])
+                .map_err(std::io::Error::other)?
+                .into_rgb8();
+            let mut encoder = png::Encoder::new(writer, first.width(), first.height());
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .set_animated(snapshots.len() as u32, 0)
+                .map_err(std::io::Error::other)?;
+            encoder
+                .set_frame_delay(1, fps as u16)
+                .map_err(std::io::Error::other)?;
+            let mut writer = encoder.write_header().map_err(std::io::Error::other)?;
+
+            writer
+                .write_image_data(first.as_raw())
+                .map_err(std::io::Error::other)?;
+            for snapshot in &snapshots[1..] {
+                let frame = image::open(snapshot)
+                    .map_err(std::io::Error::other)?
+                    .into_rgb8();
+                if frame.dimensions() != first.dimensions() {
+                    return Err(std::io::Error::other(format!(
+                        "Snapshot {} has a different size than the first snapshot, can't add it to the timelapse",
+                        snapshot.display()
+                    )));
+                }
+                writer
+                    .write_image_data(frame.as_raw())
+                    .map_err(std::io::Error::other)?;
+            }
+            writer.finish().map_err(std::io::Error::other)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use image::{codecs::gif::GifDecoder, AnimationDecoder};
+    use rstest::rstest;
+
+    fn snapshot_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("breakwater_test_{}_{name}", std::process::id()));
+        // Left over from a previous run
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn snapshot_sink(
+        directory: &Path,
+        max_snapshots: Option<usize>,
+        timelapse_format: Option<TimelapseFormat>,
+    ) -> SnapshotSink {
+        let fb = Arc::new(FrameBuffer::new(4, 3));
+        fb.set(1, 2, 0x123456);
+        SnapshotSink {
+            fb,
+            directory: directory.to_path_buf(),
+            interval: Duration::from_secs(1),
+            max_snapshots,
+            timelapse_format,
+            timelapse_fps: 10,
+        }
+    }
+
+    /// Creates some snapshots with made up timestamps, as taking real ones would require waiting for a second each
+    fn create_fake_snapshots(directory: &Path, count: usize) {
+        for second in 0..count {
+            RgbImage::new(4, 3)
+                .save(directory.join(format!(
+                    "{SNAPSHOT_FILE_PREFIX}2000-01-01_00-00-{second:02}{SNAPSHOT_FILE_SUFFIX}"
+                )))
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_take_snapshot() {
+        let directory = snapshot_directory("take_snapshot");
+        let sink = snapshot_sink(&directory, None, None);
+        sink.take_snapshot().await.unwrap();
+
+        let snapshots = list_snapshots(&directory).unwrap();
+        assert_eq!(snapshots.len(), 1);
+        let snapshot = image::open(&snapshots[0]).unwrap().into_rgb8();
+        assert_eq!(snapshot.dimensions(), (4, 3));
+        assert_eq!(snapshot.get_pixel(1, 2).0, [0x56, 0x34, 0x12]);
+        assert_eq!(snapshot.get_pixel(0, 0).0, [0, 0, 0]);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_oldest_snapshots_are_deleted() {
+        let directory = snapshot_directory("oldest_snapshots_are_deleted");
+        create_fake_snapshots(&directory, 5);
+        let sink = snapshot_sink(&directory, Some(3), None);
+        sink.take_snapshot().await.unwrap();
+
+        let snapshots = list_snapshots(&directory)
+            .unwrap()
+            .into_iter()
+            .map(|snapshot| snapshot.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(snapshots.len(), 3);
+        assert!(snapshots[0].ends_with("00-00-03.png"));
+        assert!(snapshots[1].ends_with("00-00-04.png"));
+        assert!(!snapshots[2].starts_with(&format!("{SNAPSHOT_FILE_PREFIX}2000")));
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_keep_taking_snapshots_after_failure() {
+        let directory = snapshot_directory("keep_taking_snapshots_after_failure");
+        let sink = Arc::new(SnapshotSink {
+            // Long enough that we don't remove the directory in the middle of taking a snapshot
+            interval: Duration::from_millis(200),
+            ..snapshot_sink(&directory, None, None)
+        });
+        let running_sink = Arc::clone(&sink);
+        let snapshots = tokio::spawn(async move { running_sink.take_snapshots().await });
+
+        wait_for_snapshot(&directory).await;
+        // Taking snapshots fails while the directory is missing
+        std::fs::remove_dir_all(&directory).unwrap();
+        time::sleep(Duration::from_millis(500)).await;
+        assert!(!snapshots.is_finished());
+
+        std::fs::create_dir_all(&directory).unwrap();
+        wait_for_snapshot(&directory).await;
+
+        snapshots.abort();
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    async fn wait_for_snapshot(directory: &Path) {
+        time::timeout(Duration::from_secs(5), async {
+            while list_snapshots(directory).map_or(true, |snapshots| snapshots.is_empty()) {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("No snapshot taken");
+    }
+
+    #[rstest]
+    #[case(TimelapseFormat::Gif)]
+    #[case(TimelapseFormat::Apng)]
+    #[tokio::test]
+    async fn test_timelapse(#[case] format: TimelapseFormat) {
+        let directory = snapshot_directory(&format!("timelapse_{format:?}"));
+        create_fake_snapshots(&directory, 5);
+        let sink = snapshot_sink(&directory, None, Some(format));
+        sink.assemble_timelapse().await.unwrap();
+
+        let timelapse = std::fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| path.to_string_lossy().contains("breakwater_timelapse_"))
+            .expect("No timelapse written");
+        let frames = match format {
+            TimelapseFormat::Gif => GifDecoder::new(File::open(&timelapse).unwrap())
+                .unwrap()
+                .into_frames()
+                .count() as u32,
+            TimelapseFormat::Apng => {
+                let reader = png::Decoder::new(File::open(&timelapse).unwrap())
+                    .read_info()
+                    .unwrap();
+                reader.info().animation_control().unwrap().num_frames
+            }
+        };
+        assert_eq!(frames, 5);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_no_timelapse_without_snapshots() {
+        let directory = snapshot_directory("no_timelapse_without_snapshots");
+        let sink = snapshot_sink(&directory, None, Some(TimelapseFormat::Gif));
+        sink.assemble_timelapse().await.unwrap();
+
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,12 +1,15 @@
+use crate::args::Args;
 use crate::framebuffer::FrameBuffer;
+use crate::sinks::{Sink, SinkContext, SinkFuture};
 use crate::statistics::{StatisticsEvent, StatisticsInformationEvent};
 use core::slice;
 use number_prefix::NumberPrefix;
 use rusttype::{point, Font, Scale};
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::broadcast;
+use thread_priority::{ThreadBuilderExt, ThreadPriority};
 use tokio::sync::mpsc::Sender;
+use tokio::sync::{broadcast, oneshot};
 use vncserver::{
     rfb_framebuffer_malloc, rfb_get_screen, rfb_init_server, rfb_mark_rect_as_modified,
     rfb_run_event_loop, RfbScreenInfoPtr,
@@ -14,6 +17,76 @@ use vncserver::{
 
 const STATS_HEIGHT: usize = 35;
 
+/// Runs the [`VncServer`] on a dedicated OS thread with a high priority, as libvncserver is not async.
+pub struct VncSink {
+    fb: Arc<FrameBuffer>,
+    port: u32,
+    target_fps: u32,
+
+    statistics_tx: Sender<StatisticsEvent>,
+    statistics_information_tx: broadcast::Sender<StatisticsInformationEvent>,
+
+    text: String,
+    font: String,
+}
+
+impl VncSink {
+    pub fn new(args: &Args, context: &SinkContext) -> Self {
+        VncSink {
+            fb: Arc::clone(&context.fb),
+            port: args.vnc_port,
+            target_fps: args.fps,
+            statistics_tx: context.statistics_tx.clone(),
+            statistics_information_tx: context.statistics_information_tx.clone(),
+            text: args.text.clone(),
+            font: args.font.clone(),
+        }
+    }
+}
+
+impl Sink for VncSink {
+    fn name(&self) -> &str {
+        "VNC"
+    }
+
+    fn run(&self) -> SinkFuture<'_> {
+        let fb = Arc::clone(&self.fb);
+        let port = self.port;
+        let target_fps = self.target_fps;
+        let statistics_tx = self.statistics_tx.clone();
+        let statistics_information_rx = self.statistics_information_tx.subscribe();
+        let text = self.text.clone();
+        let font = self.font.clone();
+        // Gets dropped when the thread ends, e.g. because it panicked
+        let (thread_alive_tx, thread_alive_rx) = oneshot::channel::<()>();
+
+        let thread = std::thread::Builder::new()
+            .name("breakwater vnc server thread".to_owned())
+            .spawn_with_priority(
+                ThreadPriority::Crossplatform(70.try_into().expect("Failed to get cross-platform ThreadPriority. Please report this error message together with your operating system.")),
+                move |_| {
+                    let _thread_alive_tx = thread_alive_tx;
+                    let mut vnc_server = VncServer::new(
+                        fb,
+                        port,
+                        target_fps,
+                        statistics_tx,
+                        statistics_information_rx,
+                        &text,
+                        &font,
+                    );
+                    vnc_server.run();
+                },
+            );
+
+        Box::pin(async move {
+            thread?;
+            let _ = thread_alive_rx.await;
+            Err(std::io::Error::other("VNC server thread stopped"))
+        })
+    }
+}
+
 pub struct VncServer<'a> {
     fb: Arc<FrameBuffer>,
     screen: RfbScreenInfoPtr,
@@ -82,19 +155,22 @@ impl<'a> VncServer<'a> {
     pub fn run(&mut self) {
         let target_loop_duration = Duration::from_micros(1_000_000 / self.target_fps as u64);
 
-        let fb = &self.fb;
+        let fb = Arc::clone(&self.fb);
         let vnc_fb_slice: &mut [u32] = unsafe {
             slice::from_raw_parts_mut((*self.screen).frameBuffer as *mut u32, fb.get_size())
         };
-        let fb_slice = unsafe { &*fb.get_buffer() };
         // A line less because the (height - STATS_SURFACE_HEIGHT) belongs to the stats and gets refreshed by them
         let height_up_to_stats_text = self.fb.get_height() - STATS_HEIGHT - 1;
-        let fb_size_up_to_stats_text = fb.get_width() * height_up_to_stats_text;
 
         loop {
             let start = std::time::Instant::now();
-            vnc_fb_slice[0..fb_size_up_to_stats_text]
-                .copy_from_slice(&fb_slice[0..fb_size_up_to_stats_text]);
+            for (y, vnc_row) in vnc_fb_slice
+                .chunks_exact_mut(fb.get_width())
+                .take(height_up_to_stats_text)
+                .enumerate()
+            {
+                fb.copy_row(y, vnc_row);
+            }
 
             // Only refresh the drawing surface, not the stats surface
             rfb_mark_rect_as_modified(
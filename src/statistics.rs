@@ -9,15 +9,41 @@ use std::{
 };
 use tokio::sync::{broadcast, mpsc::Receiver};
 
+use crate::sinks::SinkState;
+
 pub const STATS_REPORT_INTERVAL: Duration = Duration::from_millis(1000);
 pub const STATS_SLIDING_WINDOW_SIZE: usize = 5;
 
 #[derive(Debug)]
 pub enum StatisticsEvent {
-    ConnectionCreated { ip: IpAddr },
-    ConnectionClosed { ip: IpAddr },
-    BytesRead { ip: IpAddr, bytes: u64 },
+    ConnectionCreated {
+        ip: IpAddr,
+    },
+    ConnectionClosed {
+        ip: IpAddr,
+    },
+    BytesRead {
+        ip: IpAddr,
+        bytes: u64,
+    },
+    /// Pixels set by the client, RECT commands count with their full area
+    PixelsDrawn {
+        ip: IpAddr,
+        pixels: u64,
+    },
+    /// Reads from the connection were delayed for the given duration, as the IP exceeded the rate limit
+    Throttled {
+        ip: IpAddr,
+        duration: Duration,
+    },
     FrameRendered,
+    SinkStateChanged {
+        sink: String,
+        state: SinkState,
+    },
+    SinkRestarted {
+        sink: String,
+    },
 }
 
 pub enum StatisticsSaveMode {
@@ -38,6 +64,22 @@ pub struct StatisticsInformationEvent {
     pub connections_for_ip: HashMap<IpAddr, u32>,
     pub bytes_for_ip: HashMap<IpAddr, u64>,
 
+    // Older save files don't contain the sinks
+    #[serde(default)]
+    pub sink_states: HashMap<String, SinkState>,
+    #[serde(default)]
+    pub sink_restarts: HashMap<String, u64>,
+
+    #[serde(default)]
+    pub throttled_ms_for_ip: HashMap<IpAddr, u64>,
+
+    #[serde(default)]
+    pub pixels: u64,
+    #[serde(default)]
+    pub pixels_per_s: u64,
+    #[serde(default)]
+    pub pixels_for_ip: HashMap<IpAddr, u64>,
+
     pub statistic_events: u64,
 }
 
@@ -49,8 +91,13 @@ pub struct Statistics {
     frame: u64,
     connections_for_ip: HashMap<IpAddr, u32>,
     bytes_for_ip: HashMap<IpAddr, u64>,
+    sink_states: HashMap<String, SinkState>,
+    sink_restarts: HashMap<String, u64>,
+    throttled_ms_for_ip: HashMap<IpAddr, u64>,
+    pixels_for_ip: HashMap<IpAddr, u64>,
 
     bytes_per_s_window: SingleSumSMA<u64, u64, STATS_SLIDING_WINDOW_SIZE>,
+    pixels_per_s_window: SingleSumSMA<u64, u64, STATS_SLIDING_WINDOW_SIZE>,
     fps_window: SingleSumSMA<u64, u64, STATS_SLIDING_WINDOW_SIZE>,
 
     statistics_save_mode: StatisticsSaveMode,
@@ -85,7 +132,12 @@ impl Statistics {
             frame: 0,
             connections_for_ip: HashMap::new(),
             bytes_for_ip: HashMap::new(),
+            sink_states: HashMap::new(),
+            sink_restarts: HashMap::new(),
+            throttled_ms_for_ip: HashMap::new(),
+            pixels_for_ip: HashMap::new(),
             bytes_per_s_window: SingleSumSMA::new(),
+            pixels_per_s_window: SingleSumSMA::new(),
             fps_window: SingleSumSMA::new(),
             statistics_save_mode,
         };
@@ -95,6 +147,7 @@ impl Statistics {
                 statistics.statistic_events = save_point.statistic_events;
                 statistics.frame = save_point.frame;
                 statistics.bytes_for_ip = save_point.bytes_for_ip;
+                statistics.pixels_for_ip = save_point.pixels_for_ip;
             }
         }
 
@@ -124,7 +177,19 @@ impl Statistics {
                 StatisticsEvent::BytesRead { ip, bytes } => {
                     *self.bytes_for_ip.entry(ip).or_insert(0) += bytes;
                 }
+                StatisticsEvent::PixelsDrawn { ip, pixels } => {
+                    *self.pixels_for_ip.entry(ip).or_insert(0) += pixels;
+                }
+                StatisticsEvent::Throttled { ip, duration } => {
+                    *self.throttled_ms_for_ip.entry(ip).or_insert(0) += duration.as_millis() as u64;
+                }
                 StatisticsEvent::FrameRendered => self.frame += 1,
+                StatisticsEvent::SinkStateChanged { sink, state } => {
+                    self.sink_states.insert(sink, state);
+                }
+                StatisticsEvent::SinkRestarted { sink } => {
+                    *self.sink_restarts.entry(sink).or_insert(0) += 1;
+                }
             }
 
             // As there is an event for every frame we are guaranteed to land here every second
@@ -172,6 +237,9 @@ impl Statistics {
         let bytes = self.bytes_for_ip.values().sum();
         self.bytes_per_s_window
             .add_sample((bytes - prev.bytes) * 1000 / elapsed_ms);
+        let pixels = self.pixels_for_ip.values().sum();
+        self.pixels_per_s_window
+            .add_sample((pixels - prev.pixels) * 1000 / elapsed_ms);
         self.fps_window
             .add_sample((frame - prev.frame) * 1000 / elapsed_ms);
         let statistic_events = self.statistic_events;
@@ -186,6 +254,12 @@ impl Statistics {
             bytes_per_s: self.bytes_per_s_window.get_average(),
             connections_for_ip: self.connections_for_ip.clone(),
             bytes_for_ip: self.bytes_for_ip.clone(),
+            sink_states: self.sink_states.clone(),
+            sink_restarts: self.sink_restarts.clone(),
+            throttled_ms_for_ip: self.throttled_ms_for_ip.clone(),
+            pixels,
+            pixels_per_s: self.pixels_per_s_window.get_average(),
+            pixels_for_ip: self.pixels_for_ip.clone(),
             statistic_events,
         }
     }
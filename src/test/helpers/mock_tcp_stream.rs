@@ -1,5 +1,6 @@
 use std::{
     cmp::min,
+    collections::VecDeque,
     io::{Read, Write},
     task::Poll,
 };
@@ -10,16 +11,44 @@ use tokio::io::{AsyncRead, AsyncWrite};
 pub struct MockTcpStream {
     read_data: Vec<u8>,
     write_data: Vec<u8>,
+    /// Maximum number of bytes returned by the next reads. Once empty, reads return as much as possible.
+    read_chunk_sizes: VecDeque<usize>,
 }
 
 impl MockTcpStream {
     pub fn from_input(input: &str) -> Self {
+        Self::from_bytes(input.as_bytes())
+    }
+
+    pub fn from_bytes(input: &[u8]) -> Self {
         MockTcpStream {
-            read_data: input.as_bytes().to_vec(),
+            read_data: input.to_vec(),
             write_data: Vec::new(),
+            read_chunk_sizes: VecDeque::new(),
+        }
+    }
+
+    /// Splits the input the same way a real socket might: Every read returns at most the next chunk size.
+    /// Chunk sizes of 0 are treated as 1, as a read of 0 bytes signals a closed connection.
+    pub fn from_chunks(input: &[u8], chunk_sizes: &[usize]) -> Self {
+        MockTcpStream {
+            read_chunk_sizes: chunk_sizes.iter().map(|size| (*size).max(1)).collect(),
+            ..Self::from_bytes(input)
         }
     }
 
+    fn next_read_size(&mut self, buffer_size: usize) -> usize {
+        let size = min(self.read_data.len(), buffer_size);
+        match self.read_chunk_sizes.pop_front() {
+            Some(chunk_size) => min(size, chunk_size),
+            None => size,
+        }
+    }
+
+    pub fn get_output_bytes(self) -> Vec<u8> {
+        self.write_data
+    }
+
     pub fn get_output(self) -> String {
         String::from_utf8(self.write_data).unwrap()
     }
@@ -27,7 +56,7 @@ impl MockTcpStream {
 
 impl Read for MockTcpStream {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let size: usize = min(self.read_data.len(), buf.len());
+        let size = self.next_read_size(buf.len());
         buf[..size].copy_from_slice(&self.read_data[..size]);
 
         self.read_data.drain(..size);
@@ -52,9 +81,10 @@ impl AsyncRead for MockTcpStream {
         _cx: &mut std::task::Context<'_>,
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> std::task::Poll<std::io::Result<()>> {
-        let size: usize = min(self.read_data.len(), buf.remaining());
-        buf.put_slice(&self.read_data[..size]);
-        self.get_mut().read_data.drain(..size);
+        let this = self.get_mut();
+        let size = this.next_read_size(buf.remaining());
+        buf.put_slice(&this.read_data[..size]);
+        this.read_data.drain(..size);
         std::task::Poll::Ready(Ok(()))
     }
 }
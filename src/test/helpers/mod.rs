@@ -1,7 +1,9 @@
 mod dev_null_tcp_stream;
 mod mock_tcp_stream;
+mod parser_equivalence;
 mod pixelflut_commands;
 
 pub use dev_null_tcp_stream::*;
 pub use mock_tcp_stream::*;
+pub use parser_equivalence::*;
 pub use pixelflut_commands::*;
@@ -0,0 +1,75 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+};
+
+use tokio::sync::mpsc;
+
+use crate::{
+    framebuffer::FrameBuffer,
+    network::handle_connection,
+    parser::{original::OriginalParser, reference::ReferenceParser, Parser},
+    rate_limiter::RateLimiter,
+    test::helpers::MockTcpStream,
+};
+
+/// Small enough to be fast, but big enough that clients can hit the screen as well as miss it
+pub const EQUIVALENCE_FRAMEBUFFER_WIDTH: usize = 64;
+pub const EQUIVALENCE_FRAMEBUFFER_HEIGHT: usize = 48;
+pub const EQUIVALENCE_MAX_RECT_AREA: usize = 16 * 16;
+
+/// Feeds `input` through [`handle_connection`] once using the [`OriginalParser`] and once using the [`ReferenceParser`].
+/// The socket returns the input in chunks of the given sizes, the same way a real socket might split it.
+/// The [`OriginalParser`] draws on a padded framebuffer, so that the unchecked writes are covered as well.
+///
+/// Panics if the parsers produce different responses, framebuffer contents or parser states.
+pub async fn assert_parsers_are_equivalent(input: &[u8], chunk_sizes: &[usize]) {
+    let original_fb = Arc::new(FrameBuffer::new_padded(
+        EQUIVALENCE_FRAMEBUFFER_WIDTH,
+        EQUIVALENCE_FRAMEBUFFER_HEIGHT,
+    ));
+    let reference_fb = Arc::new(FrameBuffer::new(
+        EQUIVALENCE_FRAMEBUFFER_WIDTH,
+        EQUIVALENCE_FRAMEBUFFER_HEIGHT,
+    ));
+
+    let mut original_parser =
+        OriginalParser::new_padded(Arc::clone(&original_fb), EQUIVALENCE_MAX_RECT_AREA);
+    let mut reference_parser =
+        ReferenceParser::new(Arc::clone(&reference_fb), EQUIVALENCE_MAX_RECT_AREA);
+
+    let original_output = run_connection(input, chunk_sizes, &mut original_parser).await;
+    let reference_output = run_connection(input, chunk_sizes, &mut reference_parser).await;
+
+    assert_eq!(
+        String::from_utf8_lossy(&original_output),
+        String::from_utf8_lossy(&reference_output),
+        "Parsers produced different responses"
+    );
+    assert!(
+        original_fb.snapshot() == reference_fb.snapshot(),
+        "Parsers produced different framebuffer contents"
+    );
+    assert_eq!(
+        original_parser.state(),
+        reference_parser.state(),
+        "Parsers ended up in different states"
+    );
+}
+
+async fn run_connection(input: &[u8], chunk_sizes: &[usize], parser: &mut impl Parser) -> Vec<u8> {
+    // The receiver needs to be kept alive, otherwise sending statistics fails
+    let (statistics_tx, _statistics_rx) = mpsc::channel(100);
+    let mut stream = MockTcpStream::from_chunks(input, chunk_sizes);
+
+    handle_connection(
+        &mut stream,
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        statistics_tx,
+        &Arc::new(RateLimiter::unlimited()),
+        parser,
+    )
+    .await;
+
+    stream.get_output_bytes()
+}
@@ -10,6 +10,39 @@ pub fn get_commands_to_draw_rect(width: usize, height: usize, color: u32) -> Str
     draw_commands
 }
 
+pub fn get_commands_to_draw_rect_with_alpha(
+    width: usize,
+    height: usize,
+    color: u32,
+    alpha: u8,
+) -> String {
+    let mut draw_commands = String::new();
+
+    for x in 0..width {
+        for y in 0..height {
+            draw_commands += &format!("PX {x} {y} {color:06x}{alpha:02x}\n");
+        }
+    }
+
+    draw_commands
+}
+
+pub fn get_binary_commands_to_draw_rect(width: usize, height: usize, color: u32) -> Vec<u8> {
+    let mut draw_commands = Vec::new();
+
+    for x in 0..width {
+        for y in 0..height {
+            draw_commands.extend_from_slice(b"PB");
+            draw_commands.extend_from_slice(&(x as u16).to_le_bytes());
+            draw_commands.extend_from_slice(&(y as u16).to_le_bytes());
+            // Color is given as rrggbb, but the binary command takes the bytes r, g, b and a
+            draw_commands.extend_from_slice(&(color << 8 | 0xff).to_be_bytes());
+        }
+    }
+
+    draw_commands
+}
+
 pub fn get_commands_to_read_rect(width: usize, height: usize) -> String {
     let mut read_commands = String::new();
 
@@ -0,0 +1,310 @@
+use crate::{
+    framebuffer::FrameBuffer,
+    network::{
+        close_when_denied, handle_connection_with_parser, ip_to_canonical, reject_connection,
+        ConnectionPolicy,
+    },
+    statistics::StatisticsEvent,
+};
+use futures_util::{SinkExt, StreamExt};
+use log::{debug, info};
+use std::sync::Arc;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, DuplexStream},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::Sender,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+/// Size of the in-memory pipe between the WebSocket and the parser in each direction
+const BRIDGE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Pixelflut server for browser clients, which can't open raw TCP connections.
+///
+/// The payload of all WebSocket messages is fed into the same parser as the TCP connections of [`crate::network::Network`].
+/// Every text message is handled as complete commands, so a missing trailing newline is added.
+/// Binary messages (e.g. containing `PB` commands) are passed through as is.
+/// Responses of the parser are sent back as text messages, one per line.
+pub struct WebSocketNetwork {
+    listen_address: String,
+    fb: Arc<FrameBuffer>,
+    statistics_tx: Sender<StatisticsEvent>,
+    policy: ConnectionPolicy,
+}
+
+impl WebSocketNetwork {
+    pub fn new(
+        listen_address: &str,
+        fb: Arc<FrameBuffer>,
+        statistics_tx: Sender<StatisticsEvent>,
+        policy: ConnectionPolicy,
+    ) -> Self {
+        WebSocketNetwork {
+            listen_address: listen_address.to_string(),
+            fb,
+            statistics_tx,
+            policy,
+        }
+    }
+
+    pub async fn listen(&self) -> tokio::io::Result<()> {
+        let listener = TcpListener::bind(&self.listen_address).await?;
+        info!(
+            "Started Pixelflut WebSocket server on {}",
+            self.listen_address
+        );
+
+        self.serve(listener).await
+    }
+
+    pub async fn serve(&self, listener: TcpListener) -> tokio::io::Result<()> {
+        loop {
+            let (socket, socket_addr) = listener.accept().await?;
+            let ip = ip_to_canonical(socket_addr.ip());
+
+            if !self.policy.access_list.is_allowed(ip) {
+                reject_connection(
+                    ip,
+                    "it is denied by the access list",
+                    &self.policy.connection_limiter,
+                );
+                continue;
+            }
+            // WebSocket connections count towards the same limit as TCP connections
+            let Some(connection_guard) = self.policy.connection_limiter.try_acquire(ip) else {
+                reject_connection(
+                    ip,
+                    "it has too many connections",
+                    &self.policy.connection_limiter,
+                );
+                continue;
+            };
+
+            let fb = Arc::clone(&self.fb);
+            let statistics_tx = self.statistics_tx.clone();
+            let policy = self.policy.clone();
+            tokio::spawn(async move {
+                let _connection_guard = connection_guard;
+                let (parser_stream, bridge_stream) = tokio::io::duplex(BRIDGE_BUFFER_SIZE);
+                let connection = handle_connection_with_parser(
+                    parser_stream,
+                    ip,
+                    fb,
+                    statistics_tx.clone(),
+                    &policy.rate_limiter,
+                    policy.max_rect_area,
+                    policy.parser_implementation,
+                );
+                let bridged_connection = async {
+                    let (_, bridge_result) =
+                        tokio::join!(connection, bridge(socket, bridge_stream));
+                    if let Err(err) = bridge_result {
+                        debug!("WebSocket connection from {ip} failed: {err}");
+                    }
+                };
+                close_when_denied(bridged_connection, ip, &policy.access_list, &statistics_tx).await
+            });
+        }
+    }
+}
+
+/// Copies the payloads of the WebSocket messages into the parser and the responses of the parser back into messages.
+async fn bridge(socket: TcpStream, bridge_stream: DuplexStream) -> std::io::Result<()> {
+    let (bridge_rx, bridge_tx) = tokio::io::split(bridge_stream);
+    // Without a successful handshake the parser would wait forever, so we need to close the bridge in any case
+    let websocket = match tokio_tungstenite::accept_async(socket).await {
+        Ok(websocket) => websocket,
+        Err(err) => {
+            close_bridge(bridge_tx).await;
+            return Err(std::io::Error::other(err));
+        }
+    };
+    let (websocket_tx, websocket_rx) = websocket.split();
+
+    let (incoming, outgoing) = tokio::join!(
+        forward_to_parser(websocket_rx, bridge_tx),
+        forward_to_websocket(bridge_rx, websocket_tx)
+    );
+    incoming.and(outgoing)
+}
+
+async fn forward_to_parser(
+    mut websocket_rx: impl StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+        + Unpin,
+    mut bridge_tx: tokio::io::WriteHalf<DuplexStream>,
+) -> std::io::Result<()> {
+    let mut result = Ok(());
+    while let Some(message) = websocket_rx.next().await {
+        let written = match message {
+            Ok(Message::Text(mut text)) => {
+                if !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                bridge_tx.write_all(text.as_bytes()).await
+            }
+            Ok(Message::Binary(data)) => bridge_tx.write_all(&data).await,
+            Ok(Message::Close(_)) => break,
+            // Pings are answered by tungstenite
+            Ok(_) => Ok(()),
+            Err(err) => Err(std::io::Error::other(err)),
+        };
+        if let Err(err) = written {
+            result = Err(err);
+            break;
+        }
+    }
+
+    // Lets the parser know that the client is gone
+    close_bridge(bridge_tx).await;
+    result
+}
+
+async fn forward_to_websocket(
+    mut bridge_rx: tokio::io::ReadHalf<DuplexStream>,
+    mut websocket_tx: impl SinkExt<Message, Error = tokio_tungstenite::tungstenite::Error> + Unpin,
+) -> std::io::Result<()> {
+    let mut buffer = vec![0; BRIDGE_BUFFER_SIZE];
+    // Reads don't respect line boundaries, so the start of a line is kept here until the rest of it arrives
+    let mut pending = Vec::new();
+    loop {
+        let read = bridge_rx.read(&mut buffer).await?;
+        if read == 0 {
+            // The parser finished, e.g. because the client closed the connection
+            break;
+        }
+
+        pending.extend_from_slice(&buffer[..read]);
+        let Some(last_newline) = pending.iter().rposition(|byte| *byte == b'\n') else {
+            continue;
+        };
+        for line in pending[..=last_newline].split_inclusive(|byte| *byte == b'\n') {
+            websocket_tx
+                .feed(response_message(line))
+                .await
+                .map_err(std::io::Error::other)?;
+        }
+        websocket_tx.flush().await.map_err(std::io::Error::other)?;
+        pending.drain(..=last_newline);
+    }
+
+    // Responses always end with a newline, but let's not swallow anything
+    if !pending.is_empty() {
+        websocket_tx
+            .send(response_message(&pending))
+            .await
+            .map_err(std::io::Error::other)?;
+    }
+
+    // The client might have closed the connection already
+    let _ = websocket_tx.close().await;
+    Ok(())
+}
+
+fn response_message(response: &[u8]) -> Message {
+    match std::str::from_utf8(response) {
+        Ok(text) => Message::Text(text.to_string()),
+        Err(_) => Message::Binary(response.to_vec()),
+    }
+}
+
+async fn close_bridge(mut bridge_tx: tokio::io::WriteHalf<DuplexStream>) {
+    // Fails if the parser already stopped, which is fine
+    let _ = bridge_tx.shutdown().await;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::parser::ParserImplementation;
+    use rstest::rstest;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+
+    #[rstest]
+    #[case(ParserImplementation::Original)]
+    #[case(ParserImplementation::Reference)]
+    #[tokio::test]
+    async fn test_websocket_client(#[case] parser_implementation: ParserImplementation) {
+        let fb = Arc::new(FrameBuffer::new(1920, 1080));
+        let (statistics_tx, mut statistics_rx) = mpsc::channel(100);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let network = WebSocketNetwork::new(
+            &address.to_string(),
+            Arc::clone(&fb),
+            statistics_tx,
+            ConnectionPolicy::unlimited(100 * 100, parser_implementation),
+        );
+        tokio::spawn(async move { network.serve(listener).await });
+
+        let (mut websocket, _) = tokio_tungstenite::connect_async(format!("ws://{address}"))
+            .await
+            .unwrap();
+        // Text messages don't need a trailing newline
+        websocket
+            .send(Message::Text("SIZE".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(next_text(&mut websocket).await, "SIZE 1920 1080\n");
+
+        websocket
+            .send(Message::Text("PX 1 2 abcdef\nPX 1 2\n".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(next_text(&mut websocket).await, "PX 1 2 abcdef\n");
+
+        websocket
+            .send(Message::Binary(
+                b"PB\x03\x00\x04\x00\x12\x34\x56\xff".to_vec(),
+            ))
+            .await
+            .unwrap();
+        websocket
+            .send(Message::Text("PX 3 4".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(next_text(&mut websocket).await, "PX 3 4 123456\n");
+
+        // Every line is sent as its own message, no matter how the parser writes them
+        websocket
+            .send(Message::Text("PX 3 4\n".repeat(10_000)))
+            .await
+            .unwrap();
+        for _ in 0..10_000 {
+            assert_eq!(next_text(&mut websocket).await, "PX 3 4 123456\n");
+        }
+
+        websocket.close(None).await.unwrap();
+
+        // WebSocket clients show up in the statistics like TCP clients
+        let ip: std::net::IpAddr = "127.0.0.1".parse().unwrap();
+        assert!(matches!(
+            statistics_rx.recv().await,
+            Some(StatisticsEvent::ConnectionCreated { ip: created }) if created == ip
+        ));
+        let closed = tokio::time::timeout(Duration::from_secs(5), async {
+            while let Some(event) = statistics_rx.recv().await {
+                if let StatisticsEvent::ConnectionClosed { ip: closed } = event {
+                    return closed;
+                }
+            }
+            panic!("Statistics channel closed");
+        })
+        .await
+        .expect("Connection did not get closed");
+        assert_eq!(closed, ip);
+    }
+
+    async fn next_text(
+        websocket: &mut (impl StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+                  + Unpin),
+    ) -> String {
+        match tokio::time::timeout(Duration::from_secs(5), websocket.next())
+            .await
+            .expect("Timed out waiting for response")
+        {
+            Some(Ok(Message::Text(text))) => text,
+            other => panic!("Expected a text message, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Runs the [`FfmpegSink`] against a stub `ffmpeg` script, which records the arguments it got called with.
+#![cfg(unix)]
+
+use std::{
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use breakwater::{
+    args::Args,
+    framebuffer::FrameBuffer,
+    sinks::{ffmpeg::FfmpegSink, Sink, SinkContext, SinkState},
+    statistics::StatisticsEvent,
+};
+use clap::Parser;
+use tokio::sync::{broadcast, mpsc};
+
+/// Creates a directory containing an `ffmpeg` script that writes its arguments (one per line) into `args.txt`, records
+/// that it was started in `starts.txt` and exits.
+/// Returns the directory, so that it can be put onto the `PATH`.
+fn create_stub_ffmpeg() -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("breakwater_test_{}_ffmpeg", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    let script = directory.join("ffmpeg");
+    fs::write(
+        &script,
+        format!(
+            "#!/bin/sh\nprintf '%s\\n' \"$@\" > {args}\necho started >> {starts}\n",
+            args = directory.join("args.txt").display(),
+            starts = directory.join("starts.txt").display(),
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+
+    directory
+}
+
+fn starts(stub_directory: &Path) -> usize {
+    fs::read_to_string(stub_directory.join("starts.txt"))
+        .map(|starts| starts.lines().count())
+        .unwrap_or(0)
+}
+
+/// Runs the sink until ffmpeg got started `expected_starts` times and returns the arguments ffmpeg was called with.
+async fn run_sink(
+    stub_directory: &Path,
+    context: &SinkContext,
+    args: &[&str],
+    expected_starts: usize,
+) -> Vec<String> {
+    let _ = fs::remove_file(stub_directory.join("args.txt"));
+    let _ = fs::remove_file(stub_directory.join("starts.txt"));
+
+    let args = Args::parse_from([&["breakwater"], args].concat());
+    let sink = FfmpegSink::new(&args, context).expect("ffmpeg sink should be enabled");
+
+    // The sink keeps restarting ffmpeg, so it never stops on its own
+    tokio::select! {
+        _ = sink.run() => panic!("ffmpeg sink stopped"),
+        _ = async {
+            while starts(stub_directory) < expected_starts {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        } => (),
+        _ = tokio::time::sleep(Duration::from_secs(10)) => {
+            panic!("ffmpeg did not get started {expected_starts} times")
+        },
+    }
+
+    fs::read_to_string(stub_directory.join("args.txt"))
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns the value following the given option, e.g. `-f flv` returns `flv` for `-f`.
+/// Options can occur multiple times, so the last one before the output is relevant.
+fn last_value_of<'a>(ffmpeg_args: &'a [String], option: &str) -> Option<&'a str> {
+    ffmpeg_args
+        .iter()
+        .rposition(|arg| arg == option)
+        .map(|position| ffmpeg_args[position + 1].as_str())
+}
+
+// All scenarios are in a single test, as they all need to modify the PATH of the test process
+#[tokio::test]
+async fn test_ffmpeg_arguments() {
+    let stub_directory = create_stub_ffmpeg();
+    let path = std::env::var("PATH").unwrap_or_default();
+    std::env::set_var("PATH", format!("{}:{path}", stub_directory.display()));
+    let (statistics_tx, mut statistics_rx) = mpsc::channel(100);
+    let context = SinkContext {
+        fb: Arc::new(FrameBuffer::new(64, 48)),
+        statistics_tx,
+        statistics_information_tx: broadcast::channel(1).0,
+    };
+
+    // Only rtmp
+    let ffmpeg_args = run_sink(
+        &stub_directory,
+        &context,
+        &["--rtmp-address", "rtmp://127.0.0.1/live"],
+        1,
+    )
+    .await;
+    assert_eq!(last_value_of(&ffmpeg_args, "-f"), Some("flv"));
+    assert_eq!(ffmpeg_args.last().unwrap(), "rtmp://127.0.0.1/live");
+    assert_eq!(last_value_of(&ffmpeg_args, "-video_size"), Some("64x48"));
+
+    // Only video file
+    let ffmpeg_args = run_sink(&stub_directory, &context, &["--save-video-to-file"], 1).await;
+    assert_eq!(last_value_of(&ffmpeg_args, "-f"), Some("segment"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-segment_time"), Some("3600"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-strftime"), Some("1"));
+    // mp4 files are written fragmented, so that they are playable after a crash
+    assert_eq!(
+        last_value_of(&ffmpeg_args, "-segment_format_options"),
+        Some("movflags=+frag_keyframe+empty_moov+default_base_moof")
+    );
+    // ffmpeg fills in the timestamp for every segment
+    assert_eq!(
+        ffmpeg_args.last().unwrap(),
+        "./pixelflut_dump_%Y-%m-%d_%H-%M-%S.mp4"
+    );
+
+    // Both at the same time
+    let ffmpeg_args = run_sink(
+        &stub_directory,
+        &context,
+        &[
+            "--rtmp-address",
+            "rtmp://127.0.0.1/live",
+            "--save-video-to-file",
+        ],
+        1,
+    )
+    .await;
+    assert_eq!(last_value_of(&ffmpeg_args, "-f"), Some("tee"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-vcodec"), Some("libx264"));
+    assert!(ffmpeg_args.windows(2).any(|w| w == ["-map", "0:v"]));
+    assert!(ffmpeg_args.windows(2).any(|w| w == ["-map", "1:a"]));
+    let (video_file, rtmp) = ffmpeg_args.last().unwrap().split_once('|').unwrap();
+    assert_eq!(
+        video_file,
+        "[f=segment:segment_time=3600:reset_timestamps=1:strftime=1:\
+         segment_format_options=movflags=+frag_keyframe+empty_moov+default_base_moof]\
+         ./pixelflut_dump_%Y-%m-%d_%H-%M-%S.mp4"
+    );
+    assert_eq!(rtmp, "[f=flv:onfail=ignore]rtmp://127.0.0.1/live");
+
+    // Custom encoding without audio
+    let video_directory = stub_directory.join("recordings");
+    let ffmpeg_args = run_sink(
+        &stub_directory,
+        &context,
+        &[
+            "--save-video-to-file",
+            "--video-directory",
+            video_directory.to_str().unwrap(),
+            "--video-file-pattern",
+            "recording_%Y-%m-%d_%H-%M-%S.mkv",
+            "--video-segment-duration-s",
+            "600",
+            "--fps",
+            "60",
+            "--ffmpeg-video-codec",
+            "libx265",
+            "--ffmpeg-preset",
+            "slow",
+            "--ffmpeg-video-bitrate",
+            "8M",
+            "--ffmpeg-gop-size",
+            "30",
+            "--ffmpeg-output-resolution",
+            "1280x720",
+            "--ffmpeg-disable-audio",
+            "--ffmpeg-extra-args",
+            "-tune zerolatency",
+        ],
+        1,
+    )
+    .await;
+    assert_eq!(last_value_of(&ffmpeg_args, "-framerate"), Some("60"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-r"), Some("60"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-vcodec"), Some("libx265"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-preset"), Some("slow"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-b:v"), Some("8M"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-g"), Some("30"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-vf"), Some("scale=1280:720"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-tune"), Some("zerolatency"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-acodec"), None);
+    assert!(!ffmpeg_args.iter().any(|arg| arg.starts_with("anullsrc")));
+    assert!(video_directory.is_dir());
+    assert_eq!(last_value_of(&ffmpeg_args, "-segment_time"), Some("600"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-segment_format_options"), None);
+    assert_eq!(
+        ffmpeg_args.last().unwrap(),
+        video_directory
+            .join("recording_%Y-%m-%d_%H-%M-%S.mkv")
+            .to_str()
+            .unwrap()
+    );
+
+    // HLS
+    let web_stream_directory = stub_directory.join("web");
+    let ffmpeg_args = run_sink(
+        &stub_directory,
+        &context,
+        &[
+            "--web-stream-directory",
+            web_stream_directory.to_str().unwrap(),
+            "--web-stream-segment-duration-s",
+            "2",
+            "--web-stream-playlist-size",
+            "10",
+        ],
+        1,
+    )
+    .await;
+    assert!(web_stream_directory.is_dir());
+    assert_eq!(last_value_of(&ffmpeg_args, "-f"), Some("hls"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-hls_time"), Some("2"));
+    assert_eq!(last_value_of(&ffmpeg_args, "-hls_list_size"), Some("10"));
+    assert_eq!(
+        ffmpeg_args.last().unwrap(),
+        web_stream_directory.join("stream.m3u8").to_str().unwrap()
+    );
+
+    // DASH next to rtmp
+    let ffmpeg_args = run_sink(
+        &stub_directory,
+        &context,
+        &[
+            "--web-stream-directory",
+            web_stream_directory.to_str().unwrap(),
+            "--web-stream-format",
+            "dash",
+            "--rtmp-address",
+            "rtmp://127.0.0.1/live",
+        ],
+        1,
+    )
+    .await;
+    assert_eq!(last_value_of(&ffmpeg_args, "-f"), Some("tee"));
+    assert_eq!(
+        ffmpeg_args.last().unwrap(),
+        &format!(
+            "[f=dash:seg_duration=4:window_size=5]{}|[f=flv:onfail=ignore]rtmp://127.0.0.1/live",
+            web_stream_directory.join("stream.mpd").display()
+        )
+    );
+
+    // ffmpeg gets restarted after it exited
+    while statistics_rx.try_recv().is_ok() {}
+    run_sink(&stub_directory, &context, &["--save-video-to-file"], 2).await;
+    let mut restarts = 0;
+    let mut states = Vec::new();
+    while let Ok(event) = statistics_rx.try_recv() {
+        match event {
+            StatisticsEvent::SinkRestarted { sink } if sink == "ffmpeg" => restarts += 1,
+            StatisticsEvent::SinkStateChanged { sink, state } if sink == "ffmpeg" => {
+                states.push(state)
+            }
+            _ => (),
+        }
+    }
+    assert_eq!(restarts, 1);
+    // The second ffmpeg might have already exited as well
+    assert_eq!(states[..2], [SinkState::Restarting, SinkState::Running]);
+
+    std::env::set_var("PATH", path);
+    fs::remove_dir_all(&stub_directory).unwrap();
+}